@@ -0,0 +1,125 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use std::fs;
+use std::io::{self, Cursor};
+use std::path::Path;
+use tempfile::TempDir;
+use vidirr::editor::{self, Format};
+use vidirr::items::ItemTable;
+use vidirr::ops::{self, Operator};
+use vidirr::session;
+
+// Number of files of the large listings, spread over directories of
+// FILES_PER_DIR files.
+const FILES: usize = 100_000;
+const FILES_PER_DIR: usize = 100;
+
+// Names of a tree of dirs directories of files files each, directories
+// first.
+fn names(root: &Path, dirs: usize, files: usize) -> Vec<String> {
+    let dir = |d: usize| root.join(format!("dir_{:05}", d));
+    let mut names: Vec<String> = (0..dirs)
+        .map(|d| dir(d).to_string_lossy().into_owned())
+        .collect();
+    for d in 0..dirs {
+        for f in 0..files {
+            let file = dir(d).join(format!("file_{:05}", f));
+            names.push(file.to_string_lossy().into_owned());
+        }
+    }
+    names
+}
+
+fn create(root: &Path, dirs: usize, files: usize) -> Vec<String> {
+    let names = names(root, dirs, files);
+    for name in &names[..dirs] {
+        fs::create_dir(name).unwrap();
+    }
+    for name in &names[dirs..] {
+        fs::File::create(name).unwrap();
+    }
+    names
+}
+
+// The file list of names with every directory renamed.
+fn rename_dirs(list: &str) -> String {
+    list.replace("/dir_", "/renamed_")
+}
+
+fn list(c: &mut Criterion) {
+    let temp = TempDir::new().unwrap();
+    let sources = create(temp.path(), FILES / FILES_PER_DIR, FILES_PER_DIR);
+
+    c.bench_function("write 100k", |b| {
+        b.iter(|| editor::write_with_format(&mut io::sink(), &sources, &Format::default()))
+    });
+
+    let mut buffer = Vec::new();
+    editor::write_with_format(&mut buffer, &sources, &Format::default()).unwrap();
+    let edited = rename_dirs(&String::from_utf8(buffer).unwrap());
+
+    c.bench_function("parse 100k", |b| {
+        b.iter(|| session::parse(Cursor::new(&edited), &Format::default()).unwrap())
+    });
+
+    let items = editor::write_with_ids(&mut io::sink(), &sources).unwrap();
+    let parsed_lines = session::parse(Cursor::new(&edited), &Format::default()).unwrap();
+    let operator = Operator::new(items);
+
+    c.bench_function("preview 100k", |b| {
+        b.iter(|| operator.preview(&parsed_lines))
+    });
+}
+
+fn items(c: &mut Criterion) {
+    let sources = names(Path::new("root"), FILES / FILES_PER_DIR, FILES_PER_DIR);
+    let table: ItemTable = sources.iter().cloned().enumerate().collect();
+
+    c.bench_function("table 100k", |b| {
+        b.iter(|| sources.iter().cloned().enumerate().collect::<ItemTable>())
+    });
+
+    c.bench_function("table rename 1k dirs of 100k", |b| {
+        b.iter_batched(
+            || table.clone(),
+            |mut table| {
+                for name in &sources[..FILES / FILES_PER_DIR] {
+                    table.rename_dir(name, &name.replace("/dir_", "/renamed_"));
+                }
+                table
+            },
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+// Apply a list renaming every directory of a real tree, which also
+// renames the files below them.
+fn apply(c: &mut Criterion) {
+    let mut group = c.benchmark_group("apply");
+    group.sample_size(10);
+
+    group.bench_function("rename 100 dirs of 10k", |b| {
+        b.iter_batched(
+            || {
+                let temp = TempDir::new().unwrap();
+                let sources = create(temp.path(), 100, 100);
+                let mut buffer = Vec::new();
+                let items = editor::write_with_ids(&mut buffer, &sources).unwrap();
+                let edited = rename_dirs(&String::from_utf8(buffer).unwrap());
+                let parsed_lines = session::parse(Cursor::new(edited), &Format::default()).unwrap();
+                (temp, Operator::new(items), parsed_lines)
+            },
+            |(temp, mut operator, parsed_lines)| {
+                for (_, parsed_line) in parsed_lines {
+                    operator.apply_changes(parsed_line, ops::FS).unwrap();
+                }
+                temp
+            },
+            BatchSize::PerIteration,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, list, items, apply);
+criterion_main!(benches);
@@ -0,0 +1,175 @@
+use crate::{path, Sort};
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+// Name of the configuration file of a project, looked up in the target
+// directory and its parents.
+const PROJECT_FILE: &str = ".vidirr.toml";
+
+/// Default options, read from the configuration file of the user and from
+/// the `.vidirr.toml` of a project. Options left out keep their built-in
+/// defaults, and options given on the command line win.
+#[derive(Default, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Command run by the shell to edit the file list, whose path is
+    /// appended. It is used instead of `$VISUAL` and `$EDITOR`. Only read
+    /// from the configuration of the user, since a project file may come
+    /// from an untrusted checkout.
+    pub editor: Option<String>,
+    pub sort: Option<Sort>,
+    /// List names starting with a dot found in directories.
+    pub hidden: Option<bool>,
+    pub verbose: Option<bool>,
+    /// Review the changes and ask before applying them.
+    pub confirm: Option<bool>,
+}
+
+impl Config {
+    pub fn parse(input: &str) -> Result<Self> {
+        Ok(toml::from_str(input)?)
+    }
+
+    /// Read the configuration file at path, if there is one.
+    pub fn read(path: &Path) -> Result<Option<Self>> {
+        match fs::read_to_string(path) {
+            Ok(input) => Self::parse(&input)
+                .map(Some)
+                .map_err(|err| anyhow!("{}: {}", path.display(), err)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(anyhow!("{}: {}", path.display(), err)),
+        }
+    }
+
+    /// Options set in other take precedence over those of self.
+    pub fn merge(self, other: Self) -> Self {
+        Self {
+            editor: other.editor.or(self.editor),
+            sort: other.sort.or(self.sort),
+            hidden: other.hidden.or(self.hidden),
+            verbose: other.verbose.or(self.verbose),
+            confirm: other.confirm.or(self.confirm),
+        }
+    }
+
+    /// The configuration of the user merged with that of the project
+    /// containing dir.
+    pub fn load(dir: &Path) -> Result<Self> {
+        load_files(user_file().as_deref(), project_file(dir).as_deref())
+    }
+}
+
+/// `$XDG_CONFIG_HOME/vidirr/config.toml`, where `$XDG_CONFIG_HOME` defaults
+/// to `~/.config`.
+pub fn user_file() -> Option<PathBuf> {
+    let config_home = match env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_home.join("vidirr").join("config.toml"))
+}
+
+/// The closest `.vidirr.toml` in dir or one of its parents.
+pub fn project_file(dir: &Path) -> Option<PathBuf> {
+    path::resolve(dir)
+        .ancestors()
+        .map(|dir| dir.join(PROJECT_FILE))
+        .find(|file| file.is_file())
+}
+
+fn load_files(user: Option<&Path>, project: Option<&Path>) -> Result<Config> {
+    let mut config = Config::default();
+    if let Some(read) = user.map(Config::read).transpose()?.flatten() {
+        config = config.merge(read);
+    }
+    if let Some(file) = project {
+        if let Some(mut read) = Config::read(file)? {
+            // Commands are not taken from project files, the way git ignores
+            // risky keys in the configuration of a repository.
+            if read.editor.take().is_some() {
+                eprintln!(
+                    "{}: ignoring editor, it can only be set in the user configuration",
+                    file.display()
+                );
+            }
+            config = config.merge(read);
+        }
+    }
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::prelude::*;
+
+    #[test]
+    fn test_parse() {
+        let config = Config::parse(
+            r#"
+editor = "nano -w"
+sort = "dirs-first"
+hidden = false
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config,
+            Config {
+                editor: Some("nano -w".to_string()),
+                sort: Some(Sort::DirsFirst),
+                hidden: Some(false),
+                ..Default::default()
+            }
+        );
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(Config::parse("unknown = true").is_err());
+        assert!(Config::parse("sort = \"size\"").is_err());
+        assert!(Config::parse("verbose = \"yes\"").is_err());
+    }
+
+    #[test]
+    fn test_load_files() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let user = temp.child("config.toml");
+        user.write_str("verbose = true\nsort = \"name\"\neditor = \"nano\"\n")
+            .unwrap();
+        let project = temp.child("project/.vidirr.toml");
+        project
+            .write_str("sort = \"dirs-last\"\nconfirm = true\neditor = \"touch pwned\"\n")
+            .unwrap();
+        temp.child("project/sub/dir").create_dir_all().unwrap();
+
+        let found = project_file(temp.child("project/sub/dir").path()).unwrap();
+        assert_eq!(found, project.path());
+
+        let config = load_files(Some(user.path()), Some(&found)).unwrap();
+        assert_eq!(
+            config,
+            Config {
+                editor: Some("nano".to_string()),
+                sort: Some(Sort::DirsLast),
+                verbose: Some(true),
+                confirm: Some(true),
+                ..Default::default()
+            }
+        );
+
+        let missing = temp.child("missing.toml");
+        let config = load_files(Some(missing.path()), None).unwrap();
+        assert_eq!(config, Config::default());
+
+        user.write_str("verbose = 1\n").unwrap();
+        let err = load_files(Some(user.path()), None).unwrap_err();
+        assert!(err.to_string().starts_with(user.to_str().unwrap()));
+    }
+}
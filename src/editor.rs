@@ -1,36 +1,374 @@
-use anyhow::{anyhow, Result};
-use std::collections::HashMap;
-use std::io::Write;
+use crate::items::ItemTable;
+use crate::meta::{self, Columns};
+use crate::path;
+use anyhow::{anyhow, bail, Result};
+use std::collections::HashSet;
+use std::fs;
+use std::io::{BufRead, ErrorKind, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::SystemTime;
 
-pub fn write_with_ids<W: Write>(
+/// Format controls the optional parts of the buffer written by
+/// `write_with_format` and read back by `parse_line_with`.
+#[derive(Default, Debug, Clone)]
+pub struct Format {
+    /// Render symlinks as `name -> target` so the target can be edited.
+    pub links: bool,
+    /// Prefix each name with type, permission, owner, size and
+    /// modification time columns. Permission, owner and modification time
+    /// can be edited.
+    pub long: bool,
+    /// Prefix each name with an editable modification time column. Implied
+    /// by `long`.
+    pub mtime: bool,
+    /// Append comment lines explaining the syntax of the buffer.
+    pub header: bool,
+    /// How items are numbered.
+    pub ids: Ids,
+    /// Show names relative to this directory, and names outside it
+    /// absolute. Edited names are resolved against it by `Operator::base`.
+    pub base: Option<PathBuf>,
+}
+
+/// How items are numbered in the buffer.
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+pub enum Ids {
+    /// By position in the listing, starting from 1.
+    #[default]
+    Position,
+    /// By inode number, which does not change when a file is renamed, so
+    /// that lists stay meaningful across sessions.
+    Inode,
+}
+
+impl Format {
+    // Number of whitespace separated columns between the number and the
+    // name.
+    fn columns(&self) -> usize {
+        if self.long {
+            5
+        } else if self.mtime {
+            1
+        } else {
+            0
+        }
+    }
+}
+
+const LINK_SEPARATOR: &str = " -> ";
+
+const COMMENT: char = '#';
+
+const HEADER: &str = "
+# Edit the names above and save to apply the changes.
+#
+# <num> <name>  rename item <num> to <name>, the same <num> on
+#               another line copies the item
+# <name>        create an empty file, or a directory if <name> ends
+#               with /
+# + <name>      same as above, for names that start with a number
+# \\#<name>      same as above, for names that start with #
+#
+# Directories are shown with a trailing /. Lines starting with # are
+# ignored, as are lines with an empty <name>.
+";
+
+const HEADER_LINKS: &str = "#
+# Symlinks are shown as <name> -> <target>, edit <target> to change
+# where they point.
+";
+
+const HEADER_LONG: &str = "#
+# Columns are type, permissions, owner:group, size and modification
+# time. Permissions (rw-r--r-- or 0644), owner:group and modification
+# time (ISO 8601) can be edited.
+";
+
+const HEADER_MTIME: &str = "#
+# The modification time (ISO 8601) in front of the name can be edited.
+";
+
+pub fn write_with_ids<W: Write>(output: &mut W, sources: &[String]) -> std::io::Result<ItemTable> {
+    write_with_format(output, sources, &Format::default())
+}
+
+pub fn write_with_format<W: Write>(
     output: &mut W,
     sources: &[String],
-) -> std::io::Result<HashMap<usize, String>> {
-    let mut items = HashMap::new();
-    let padding = (sources.len() + 1).to_string().len();
+    format: &Format,
+) -> std::io::Result<ItemTable> {
+    let mut items = ItemTable::with_capacity(sources.len());
+    let (ids, padding) = match format.ids {
+        Ids::Position => (
+            (1..=sources.len()).collect(),
+            (sources.len() + 1).to_string().len(),
+        ),
+        Ids::Inode => {
+            let ids = inode_ids(sources)?;
+            let padding = ids.iter().max().map_or(1, |id| id.to_string().len());
+            (ids, padding)
+        }
+    };
+
+    let columns: Vec<Columns> = if format.columns() > 0 {
+        sources
+            .iter()
+            .map(|file| Columns::new(fs::symlink_metadata(file).ok().as_ref()))
+            .collect()
+    } else {
+        Vec::new()
+    };
+    let owner_width = columns.iter().map(|c| c.owner.len()).max().unwrap_or(0);
+    let size_width = columns.iter().map(|c| c.size.len()).max().unwrap_or(0);
+
+    for (i, (file, id)) in sources.iter().zip(ids).enumerate() {
+        items.insert(id, file.to_string());
 
-    for (i, file) in sources.iter().enumerate() {
-        items.insert(i + 1, file.to_string());
-        writeln!(output, "{:<p$} {}", i + 1, file, p = padding)?
+        // Numbers are right aligned because the padding would otherwise
+        // end up in the filename when parsing.
+        write!(output, "{:>p$} ", id, p = padding)?;
+
+        if let Some(c) = columns.get(i) {
+            if format.long {
+                write!(
+                    output,
+                    "{} {:>9} {:<o$} {:>s$} {:>19} ",
+                    c.file_type,
+                    c.mode,
+                    c.owner,
+                    c.size,
+                    c.mtime,
+                    o = owner_width,
+                    s = size_width
+                )?;
+            } else {
+                write!(output, "{:>19} ", c.mtime)?;
+            }
+        }
+        match &format.base {
+            Some(base) => match path::strip(file, base) {
+                Some(name) => write!(output, "{}", name)?,
+                // Names outside base are shown absolute, which resolving
+                // them against base leaves as they are.
+                None => write!(output, "{}", path::absolute(file)?.display())?,
+            },
+            None => write!(output, "{}", file)?,
+        }
+        // Links to directories are listed like other links.
+        if !file.ends_with('/') && path::is_dir(file) {
+            write!(output, "/")?;
+        }
+
+        if format.links {
+            if let Some(target) = read_link(file) {
+                write!(output, "{}{}", LINK_SEPARATOR, target)?;
+            }
+        }
+        writeln!(output)?
+    }
+
+    if format.header {
+        write_header(output, format)?;
     }
     Ok(items)
 }
 
-#[derive(PartialEq, Debug)]
+// Inode numbers of sources, which must be on the same file system for
+// the numbers to be distinct. Hard links after the first name of a file
+// and names that do not exist have no inode of their own, they are
+// numbered after the largest inode number.
+fn inode_ids(sources: &[String]) -> std::io::Result<Vec<usize>> {
+    let mut device: Option<(u64, &String)> = None;
+    let mut seen = HashSet::new();
+    let mut ids = Vec::with_capacity(sources.len());
+
+    for file in sources {
+        let metadata = match fs::symlink_metadata(file) {
+            Ok(metadata) => metadata,
+            Err(_) => {
+                ids.push(None);
+                continue;
+            }
+        };
+
+        match device {
+            Some((dev, other)) if dev != metadata.dev() => {
+                return Err(std::io::Error::other(format!(
+                    "{} and {} are on different file systems, their inode numbers may clash",
+                    other, file
+                )));
+            }
+            Some(_) => {}
+            None => device = Some((metadata.dev(), file)),
+        }
+
+        let id = metadata.ino() as usize;
+        ids.push(seen.insert(id).then_some(id));
+    }
+
+    let mut next = seen.iter().max().map_or(1, |max| max + 1);
+    Ok(ids
+        .into_iter()
+        .map(|id| {
+            id.unwrap_or_else(|| {
+                next += 1;
+                next - 1
+            })
+        })
+        .collect())
+}
+
+fn write_header<W: Write>(output: &mut W, format: &Format) -> std::io::Result<()> {
+    write!(output, "{}", HEADER)?;
+    if format.links {
+        write!(output, "{}", HEADER_LINKS)?;
+    }
+    if format.long {
+        write!(output, "{}", HEADER_LONG)?;
+    } else if format.mtime {
+        write!(output, "{}", HEADER_MTIME)?;
+    }
+    Ok(())
+}
+
+/// Pipe input through the shell command and return what it prints.
+pub fn filter(command: &str, input: Vec<u8>) -> Result<Vec<u8>> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    // Write from another thread so that a command producing a lot of
+    // output before reading all input does not deadlock.
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let writer = thread::spawn(move || match stdin.write_all(&input) {
+        // The command does not have to read all of its input.
+        Err(e) if e.kind() == ErrorKind::BrokenPipe => Ok(()),
+        res => res,
+    });
+
+    let output = child.wait_with_output()?;
+    writer.join().expect("writer thread panicked")?;
+
+    if !output.status.success() {
+        bail!("{} exited nonzero, aborting", command);
+    }
+    Ok(output.stdout)
+}
+
+// Returns the target of file if it is a symlink.
+fn read_link(file: &str) -> Option<String> {
+    let metadata = fs::symlink_metadata(file).ok()?;
+    if !metadata.file_type().is_symlink() {
+        return None;
+    }
+    fs::read_link(file)
+        .ok()
+        .map(|target| target.to_string_lossy().to_string())
+}
+
+// Split count whitespace separated columns off input. The single space
+// that separates the last column from the filename is dropped.
+fn split_columns(input: &str, count: usize) -> Result<(Vec<&str>, &str)> {
+    let mut columns = Vec::with_capacity(count);
+    let mut remain = input;
+
+    for _ in 0..count {
+        let trimmed = remain.trim_start();
+        if trimmed.is_empty() {
+            return Err(anyhow!("missing columns"));
+        }
+        let end = trimmed.find(char::is_whitespace).unwrap_or(trimmed.len());
+        columns.push(&trimmed[..end]);
+        remain = &trimmed[end..];
+    }
+    Ok((columns, remain.strip_prefix(' ').unwrap_or(remain)))
+}
+
+#[derive(PartialEq, Debug, Default)]
 pub struct ParsedLine {
-    pub num: usize,
+    /// Item number, `None` for lines that create a new file.
+    pub num: Option<usize>,
     pub filename: String,
+    /// New symlink target, only set when parsing with `Format::links`.
+    pub target: Option<String>,
+    /// Permission bits, only set when parsing with `Format::long`.
+    pub mode: Option<u32>,
+    /// Owner and group ids, only set when parsing with `Format::long`.
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    /// Modification time, only set when parsing with `Format::long` or
+    /// `Format::mtime`.
+    pub mtime: Option<SystemTime>,
+}
+
+// Parse every line read from reader. Lines are returned together with
+// their line number, starting from 1. All lines that fail to parse are
+// reported at once.
+pub fn parse_buffer<R: BufRead>(reader: R, format: &Format) -> Result<Vec<(usize, ParsedLine)>> {
+    let mut parsed_lines = Vec::new();
+    let mut errors = Vec::new();
+
+    for (i, line) in reader.lines().enumerate() {
+        match parse_line_with(&line?, format) {
+            Ok(Some(parsed_line)) => parsed_lines.push((i + 1, parsed_line)),
+            Ok(None) => {}
+            Err(err) => errors.push(format!("line {}: {}", i + 1, err)),
+        }
+    }
+
+    if !errors.is_empty() {
+        bail!(errors.join("\n"));
+    }
+    Ok(parsed_lines)
+}
+
+// Lines without a number create a new file, or a directory when the name
+// ends with a slash. The name may be prefixed with `+` or `new ` to make
+// the intent explicit. Names starting with `#` must be escaped as `\#` to
+// tell them apart from comments.
+fn parse_new(input: &str) -> Result<Option<ParsedLine>> {
+    let filename = if input.starts_with(COMMENT) {
+        return Ok(None);
+    } else if let Some(remain) = input.strip_prefix('\\').filter(|r| r.starts_with(COMMENT)) {
+        remain
+    } else if let Some(remain) = input.strip_prefix('+') {
+        remain.strip_prefix(' ').unwrap_or(remain)
+    } else if let Some(remain) = input.strip_prefix("new ") {
+        remain
+    } else {
+        input
+    };
+
+    if filename.is_empty() {
+        return Err(anyhow!("no filename found"));
+    }
+
+    Ok(Some(ParsedLine {
+        num: None,
+        filename: filename.to_string(),
+        ..Default::default()
+    }))
 }
 
 pub fn parse_line(input: &str) -> Result<Option<ParsedLine>> {
+    parse_line_with(input, &Format::default())
+}
+
+pub fn parse_line_with(input: &str, format: &Format) -> Result<Option<ParsedLine>> {
     let trimmed = input.trim_start();
 
     if trimmed.is_empty() {
         return Ok(None);
     }
 
-    match trimmed.chars().position(|c| !c.is_numeric()) {
-        Some(0) => Err(anyhow!("no number found")),
+    let (num, filename) = match trimmed.chars().position(|c| !c.is_numeric()) {
+        Some(0) => return parse_new(trimmed),
         Some(idx) => {
             let remain = trimmed[idx..].chars();
             let mut peeker = remain.peekable();
@@ -42,21 +380,47 @@ pub fn parse_line(input: &str) -> Result<Option<ParsedLine>> {
                 _ => idx,
             };
 
-            Ok(Some(ParsedLine {
-                num: trimmed[..idx].parse::<usize>()?,
-                filename: trimmed[filename_idx..].to_string(),
-            }))
+            (
+                trimmed[..idx].parse::<usize>()?,
+                trimmed[filename_idx..].to_string(),
+            )
+        }
+        None => (trimmed.parse::<usize>()?, "".to_string()),
+    };
+
+    let mut parsed = ParsedLine {
+        num: Some(num),
+        filename,
+        ..Default::default()
+    };
+
+    if format.columns() > 0 && !parsed.filename.is_empty() {
+        let (columns, filename) = split_columns(&parsed.filename, format.columns())?;
+        if format.long {
+            // Type and size are read-only.
+            parsed.mode = meta::parse_mode(columns[1])?;
+            (parsed.uid, parsed.gid) = meta::parse_owner(columns[2])?;
+        }
+        parsed.mtime = meta::parse_mtime(columns[columns.len() - 1])?;
+        parsed.filename = filename.to_string();
+    }
+
+    if format.links {
+        if let Some((filename, target)) = parsed.filename.split_once(LINK_SEPARATOR) {
+            parsed.target = Some(target.to_string());
+            parsed.filename = filename.to_string();
         }
-        None => Ok(Some(ParsedLine {
-            num: trimmed.parse::<usize>()?,
-            filename: "".to_string(),
-        })),
     }
+
+    Ok(Some(parsed))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use assert_fs::prelude::*;
+    use std::io::Cursor;
+    use std::time::Duration;
 
     #[test]
     fn test_write_with_ids() {
@@ -66,7 +430,7 @@ mod tests {
             "xyz".to_string(),
         ];
 
-        let expected: HashMap<usize, String> = HashMap::from([
+        let expected = ItemTable::from([
             (1, "./src/testdata/file2".to_string()),
             (2, "./src/testdata/file1".to_string()),
             (3, "xyz".to_string()),
@@ -107,8 +471,9 @@ mod tests {
         assert_eq!(
             parsed.unwrap().unwrap(),
             ParsedLine {
-                num: 123,
+                num: Some(123),
                 filename: "".to_string(),
+                ..Default::default()
             }
         );
     }
@@ -120,8 +485,9 @@ mod tests {
         assert_eq!(
             parsed.unwrap().unwrap(),
             ParsedLine {
-                num: 123,
+                num: Some(123),
                 filename: "".to_string(),
+                ..Default::default()
             }
         );
     }
@@ -133,8 +499,9 @@ mod tests {
         assert_eq!(
             parsed.unwrap().unwrap(),
             ParsedLine {
-                num: 345,
+                num: Some(345),
                 filename: "file with space 123".to_string(),
+                ..Default::default()
             }
         );
     }
@@ -143,7 +510,39 @@ mod tests {
     fn test_parse_line_no_number() {
         let input = "     file with space 123 ";
         let parsed = parse_line(input);
-        assert_eq!(parsed.unwrap_err().to_string(), "no number found");
+        assert_eq!(
+            parsed.unwrap().unwrap(),
+            ParsedLine {
+                num: None,
+                filename: "file with space 123 ".to_string(),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_line_new() {
+        for (input, filename) in [
+            ("+ file", "file"),
+            ("+file", "file"),
+            ("+  file", " file"),
+            ("new dir/", "dir/"),
+            ("new", "new"),
+            ("+ new file", "new file"),
+        ] {
+            let parsed = parse_line(input);
+            assert_eq!(
+                parsed.unwrap().unwrap(),
+                ParsedLine {
+                    num: None,
+                    filename: filename.to_string(),
+                    ..Default::default()
+                }
+            );
+        }
+
+        let parsed = parse_line("+ ");
+        assert_eq!(parsed.unwrap_err().to_string(), "no filename found");
     }
 
     #[test]
@@ -153,9 +552,356 @@ mod tests {
         assert_eq!(
             parsed.unwrap().unwrap(),
             ParsedLine {
-                num: 345,
+                num: Some(345),
                 filename: "  file with space 123 ".to_string(),
+                ..Default::default()
             }
         );
     }
+
+    #[test]
+    fn test_write_with_format_links() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let file_1 = temp.child("file_1");
+        let link_1 = temp.child("link_1");
+        file_1.touch().unwrap();
+        link_1.symlink_to_file("file_1").unwrap();
+
+        let files = vec![
+            file_1.to_str().unwrap().to_string(),
+            link_1.to_str().unwrap().to_string(),
+        ];
+
+        let mut buffer = Vec::new();
+        let format = Format {
+            links: true,
+            ..Default::default()
+        };
+        write_with_format(&mut buffer, &files, &format).unwrap();
+
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            format!("1 {}\n2 {} -> file_1\n", files[0], files[1])
+        )
+    }
+
+    #[test]
+    fn test_parse_line_with_links() {
+        let format = Format {
+            links: true,
+            ..Default::default()
+        };
+
+        let parsed = parse_line_with("12 current -> releases/v2", &format);
+        assert_eq!(
+            parsed.unwrap().unwrap(),
+            ParsedLine {
+                num: Some(12),
+                filename: "current".to_string(),
+                target: Some("releases/v2".to_string()),
+                ..Default::default()
+            }
+        );
+
+        // Without links, the arrow is part of the filename.
+        let parsed = parse_line("12 current -> releases/v2");
+        assert_eq!(
+            parsed.unwrap().unwrap(),
+            ParsedLine {
+                num: Some(12),
+                filename: "current -> releases/v2".to_string(),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_write_with_ids_dirs() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        temp.child("dir").create_dir_all().unwrap();
+        temp.child("file").touch().unwrap();
+        let link = temp.child("link");
+        link.symlink_to_dir(temp.child("dir").path()).unwrap();
+
+        let files: Vec<String> = ["dir", "dir/", "file", "link"]
+            .iter()
+            .map(|name| format!("{}/{}", temp.to_str().unwrap(), name))
+            .collect();
+
+        let mut buffer = Vec::new();
+        write_with_ids(&mut buffer, &files).unwrap();
+
+        let t = temp.to_str().unwrap();
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            format!("1 {t}/dir/\n2 {t}/dir/\n3 {t}/file\n4 {t}/link\n")
+        );
+    }
+
+    #[test]
+    fn test_write_with_format_inode_ids() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let file_1 = temp.child("file_1");
+        file_1.touch().unwrap();
+        let file_2 = temp.child("file_2");
+        file_2.touch().unwrap();
+
+        let files = vec![
+            file_1.to_str().unwrap().to_string(),
+            file_2.to_str().unwrap().to_string(),
+        ];
+        let format = Format {
+            ids: Ids::Inode,
+            ..Default::default()
+        };
+
+        let mut buffer = Vec::new();
+        let items = write_with_format(&mut buffer, &files, &format).unwrap();
+
+        let ino_1 = fs::metadata(&file_1).unwrap().ino() as usize;
+        let ino_2 = fs::metadata(&file_2).unwrap().ino() as usize;
+        assert_eq!(
+            items,
+            ItemTable::from([(ino_1, files[0].clone()), (ino_2, files[1].clone())])
+        );
+
+        let p = ino_1.max(ino_2).to_string().len();
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            format!("{:>p$} {}\n{:>p$} {}\n", ino_1, files[0], ino_2, files[1])
+        );
+
+        // Hard links and missing files are numbered after the largest
+        // inode number.
+        let link = temp.child("link");
+        fs::hard_link(&file_1, &link).unwrap();
+        let files = vec![
+            "xyz".to_string(),
+            files[0].clone(),
+            link.to_str().unwrap().to_string(),
+        ];
+        let items = write_with_format(&mut Vec::new(), &files, &format).unwrap();
+        let ids: Vec<usize> = items.iter().map(|(num, _)| num).collect();
+        assert_eq!(ids, vec![ino_1 + 1, ino_1, ino_1 + 2]);
+
+        // Inode numbers of different file systems may clash.
+        let files = vec![files[1].clone(), "/proc".to_string()];
+        assert!(write_with_format(&mut Vec::new(), &files, &format).is_err());
+    }
+
+    #[test]
+    fn test_write_with_ids_padding() {
+        let files: Vec<String> = (1..=10).map(|i| format!("file{}", i)).collect();
+
+        let mut buffer = Vec::new();
+        write_with_ids(&mut buffer, &files).unwrap();
+
+        let buffer = String::from_utf8(buffer).unwrap();
+        let mut lines = buffer.lines();
+        assert_eq!(lines.next(), Some(" 1 file1"));
+        assert_eq!(lines.last(), Some("10 file10"));
+        assert_eq!(
+            parse_line(" 1 file1").unwrap().unwrap().filename,
+            "file1".to_string()
+        );
+    }
+
+    #[test]
+    fn test_write_with_format_base() {
+        let files = vec![
+            "./some/deep/path/file1".to_string(),
+            "./some/deep/path/dir/file2".to_string(),
+            "elsewhere".to_string(),
+        ];
+        let format = Format {
+            base: Some("some/deep/path".into()),
+            ..Default::default()
+        };
+
+        let mut buffer = Vec::new();
+        let items = write_with_format(&mut buffer, &files, &format).unwrap();
+
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            format!(
+                "1 file1\n2 dir/file2\n3 {}\n",
+                path::absolute("elsewhere").unwrap().display()
+            )
+        );
+        assert_eq!(items[&2], "./some/deep/path/dir/file2");
+    }
+
+    #[test]
+    fn test_write_with_format_long() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let file_1 = temp.child("file_1");
+        file_1.write_str("hello").unwrap();
+        temp.child("dir_1").create_dir_all().unwrap();
+
+        let files = vec![
+            file_1.to_str().unwrap().to_string(),
+            temp.child("dir_1").to_str().unwrap().to_string(),
+            "xyz".to_string(),
+        ];
+
+        let format = Format {
+            long: true,
+            ..Default::default()
+        };
+        let mut buffer = Vec::new();
+        write_with_format(&mut buffer, &files, &format).unwrap();
+
+        let buffer = String::from_utf8(buffer).unwrap();
+        let lines: Vec<&str> = buffer.lines().collect();
+        assert!(lines[0].starts_with("1 - "));
+        assert!(lines[1].starts_with("2 d "));
+        assert!(lines[2].starts_with("3 ? "));
+
+        // Columns are ignored when parsing the buffer back, directories keep
+        // their trailing slash.
+        let names = [files[0].clone(), files[1].clone() + "/", files[2].clone()];
+        for (line, name) in lines.iter().zip(names) {
+            let parsed = parse_line_with(line, &format).unwrap().unwrap();
+            assert_eq!(parsed.filename, name);
+        }
+    }
+
+    #[test]
+    fn test_parse_line_with_long() {
+        let format = Format {
+            long: true,
+            ..Default::default()
+        };
+
+        let parsed = parse_line_with(
+            "7 - rw-r----- 0:1000 1024 2023-05-01T10:00:00  file with space ",
+            &format,
+        );
+        assert_eq!(
+            parsed.unwrap().unwrap(),
+            ParsedLine {
+                num: Some(7),
+                filename: " file with space ".to_string(),
+                mode: Some(0o640),
+                uid: Some(0),
+                gid: Some(1000),
+                mtime: meta::parse_mtime("2023-05-01T10:00:00").unwrap(),
+                ..Default::default()
+            }
+        );
+
+        // Unknown columns of files that do not exist are left alone.
+        let parsed = parse_line_with("7 ? ? ? ? ? file", &format);
+        assert_eq!(
+            parsed.unwrap().unwrap(),
+            ParsedLine {
+                num: Some(7),
+                filename: "file".to_string(),
+                ..Default::default()
+            }
+        );
+
+        let parsed = parse_line_with("7 - rw-r--r-- 0:0 file", &format);
+        assert_eq!(parsed.unwrap_err().to_string(), "missing columns");
+
+        let parsed = parse_line_with("7 - rw-r--r-q 0:0 5 ? file", &format);
+        assert_eq!(
+            parsed.unwrap_err().to_string(),
+            "invalid mode \"rw-r--r-q\""
+        );
+    }
+
+    #[test]
+    fn test_parse_line_with_mtime() {
+        let format = Format {
+            mtime: true,
+            ..Default::default()
+        };
+
+        let parsed = parse_line_with("3 2023-05-01T10:00:00Z photo.jpg", &format);
+        assert_eq!(
+            parsed.unwrap().unwrap(),
+            ParsedLine {
+                num: Some(3),
+                filename: "photo.jpg".to_string(),
+                mtime: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1682935200)),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_buffer() {
+        let format = Format {
+            mtime: true,
+            ..Default::default()
+        };
+        let input = "1 ? file_1\n\n2 2023-05-01 file_2\n3 yesterday file_3\n4 2023-13-01 file_4\n";
+
+        let parsed = parse_buffer(Cursor::new(input), &format);
+        assert_eq!(
+            parsed.unwrap_err().to_string(),
+            "line 4: invalid time \"yesterday\"\nline 5: invalid time \"2023-13-01\""
+        );
+
+        let input = "1 ? file_1\n\n2 ? file_2\n";
+        let parsed = parse_buffer(Cursor::new(input), &format).unwrap();
+        let nums: Vec<(usize, usize)> = parsed.iter().map(|(i, p)| (*i, p.num.unwrap())).collect();
+        assert_eq!(nums, vec![(1, 1), (3, 2)]);
+    }
+
+    #[test]
+    fn test_write_with_format_header() {
+        let files = vec!["file_1".to_string(), "#file_2".to_string()];
+        let format = Format {
+            header: true,
+            ..Default::default()
+        };
+
+        let mut buffer = Vec::new();
+        let items = write_with_format(&mut buffer, &files, &format).unwrap();
+
+        let buffer = String::from_utf8(buffer).unwrap();
+        assert!(buffer.starts_with("1 file_1\n2 #file_2\n\n# Edit the names"));
+
+        // The header is ignored when parsing the buffer back.
+        let parsed = parse_buffer(Cursor::new(buffer), &format).unwrap();
+        let names: Vec<&str> = parsed.iter().map(|(_, p)| p.filename.as_str()).collect();
+        assert_eq!(names, vec!["file_1", "#file_2"]);
+        assert_eq!(items.len(), 2);
+    }
+
+    #[test]
+    fn test_parse_line_comment() {
+        assert!(parse_line("# comment").unwrap().is_none());
+        assert!(parse_line("   #1 comment").unwrap().is_none());
+
+        let parsed = parse_line("\\#file");
+        assert_eq!(
+            parsed.unwrap().unwrap(),
+            ParsedLine {
+                num: None,
+                filename: "#file".to_string(),
+                ..Default::default()
+            }
+        );
+
+        let parsed = parse_line("\\file");
+        assert_eq!(parsed.unwrap().unwrap().filename, "\\file".to_string());
+    }
+
+    #[test]
+    fn test_filter() {
+        let output = filter("sed s/foo/bar/", b"1 foo\n2 xyz\n".to_vec());
+        assert_eq!(output.unwrap(), b"1 bar\n2 xyz\n");
+
+        let output = filter("head -n 1", b"1 foo\n2 xyz\n".to_vec());
+        assert_eq!(output.unwrap(), b"1 foo\n");
+
+        let output = filter("exit 3", Vec::new());
+        assert_eq!(
+            output.unwrap_err().to_string(),
+            "exit 3 exited nonzero, aborting"
+        );
+    }
 }
@@ -0,0 +1,357 @@
+use crate::path;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::ffi::OsStr;
+use std::fmt;
+use std::ops::Index;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+
+// Separator of path components in keys of the path index. It sorts before
+// any byte of a name, so the keys of the items below a directory directly
+// follow the key of the directory and keys compare as plain bytes.
+const SEPARATOR: u8 = 0;
+
+/// Names of listed items by number, kept in the order they were inserted
+/// so that everything derived from the table is deterministic.
+///
+/// Items are also indexed by their resolved path. The index keeps the
+/// items below a directory next to each other, so renaming a directory
+/// only visits the items it contains.
+#[derive(Clone)]
+pub struct ItemTable {
+    // Removed items leave an empty slot so that the slots of the others
+    // stay valid.
+    slots: Vec<Option<(usize, String)>>,
+    nums: HashMap<usize, usize>,
+    paths: BTreeMap<Vec<u8>, Vec<usize>>,
+    // Relative names are resolved against the current directory when the
+    // table was created, instead of looking it up for every name.
+    cwd: Option<PathBuf>,
+}
+
+impl ItemTable {
+    pub fn new() -> Self {
+        Self::with_capacity(0)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            slots: Vec::with_capacity(capacity),
+            nums: HashMap::with_capacity(capacity),
+            paths: BTreeMap::new(),
+            cwd: env::current_dir().ok(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.nums.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nums.is_empty()
+    }
+
+    pub fn get(&self, num: &usize) -> Option<&String> {
+        let slot = self.nums.get(num)?;
+        self.slots[*slot].as_ref().map(|(_, name)| name)
+    }
+
+    pub fn contains(&self, num: &usize) -> bool {
+        self.nums.contains_key(num)
+    }
+
+    /// Set the name of num, keeping its place if it is already in the
+    /// table, and return the previous name.
+    pub fn insert(&mut self, num: usize, name: String) -> Option<String> {
+        let key = self.key(&name);
+        match self.nums.get(&num) {
+            Some(&slot) => Some(self.set(slot, name, key)),
+            None => {
+                let slot = self.slots.len();
+                self.slots.push(Some((num, name)));
+                self.nums.insert(num, slot);
+                self.paths.entry(key).or_default().push(slot);
+                None
+            }
+        }
+    }
+
+    pub fn remove(&mut self, num: &usize) -> Option<String> {
+        let slot = self.nums.remove(num)?;
+        let (_, name) = self.slots[slot].take()?;
+        self.unindex(&self.key(&name), slot);
+        Some(name)
+    }
+
+    /// The number of an item named name, in any spelling.
+    pub fn find(&self, name: &str) -> Option<usize> {
+        let slot = self.paths.get(&self.key(name))?.first()?;
+        self.slots[*slot].as_ref().map(|(num, _)| *num)
+    }
+
+    /// Whether an item is named name, is below it or is a directory
+    /// containing it, comparing whole path components.
+    pub fn overlaps(&self, name: &str) -> bool {
+        let key = self.key(name);
+        // Keys below key follow it directly, so only the first one counts.
+        let below_or_same = self
+            .paths
+            .range(key.clone()..)
+            .next()
+            .is_some_and(|(other, _)| below(other, &key).is_some());
+
+        // The root directory keeps its separator.
+        below_or_same
+            || (0..key.len())
+                .filter(|&i| key[i] == SEPARATOR)
+                .any(|i| self.paths.contains_key(&key[..i.max(1)]))
+    }
+
+    /// Items in the order they were inserted.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &String)> {
+        self.slots.iter().flatten().map(|(num, name)| (*num, name))
+    }
+
+    /// Rename the items named from, in any spelling, to to.
+    pub fn rename(&mut self, from: &str, to: &str) {
+        let slots = match self.paths.remove(&self.key(from)) {
+            Some(slots) => slots,
+            None => return,
+        };
+        let key = self.key(to);
+        for slot in slots {
+            if let Some((_, name)) = &mut self.slots[slot] {
+                *name = to.to_string();
+            }
+            self.paths.entry(key.clone()).or_default().push(slot);
+        }
+    }
+
+    /// Move the directory from and the items below it to the directory to,
+    /// comparing whole path components.
+    pub fn rename_dir(&mut self, from: &str, to: &str) {
+        let from = self.key(from);
+        let moved: Vec<(Vec<u8>, Vec<usize>)> = self
+            .paths
+            .range(from.clone()..)
+            .take_while(|(key, _)| below(key, &from).is_some())
+            .map(|(key, slots)| (key.clone(), slots.clone()))
+            .collect();
+
+        // The part of a key below from is normalized already, so the keys
+        // of the moved items are the key of to followed by that part.
+        let to_key = self.key(to);
+        for (old_key, slots) in moved {
+            self.paths.remove(&old_key);
+
+            let (name, new_key) = match below(&old_key, &from) {
+                Some(rest) if !rest.is_empty() => {
+                    let mut new_key = to_key.clone();
+                    if new_key.last() != Some(&SEPARATOR) {
+                        new_key.push(SEPARATOR);
+                    }
+                    new_key.extend_from_slice(rest);
+
+                    let rest: Vec<u8> = rest.iter().map(|&b| unkey(b)).collect();
+                    let rest = OsStr::from_bytes(&rest);
+                    let name = Path::new(to).join(rest).to_string_lossy().into_owned();
+                    (name, new_key)
+                }
+                _ => (to.to_string(), to_key.clone()),
+            };
+
+            for slot in slots {
+                if let Some((_, old)) = &mut self.slots[slot] {
+                    *old = name.clone();
+                }
+                self.paths.entry(new_key.clone()).or_default().push(slot);
+            }
+        }
+    }
+
+    // Replace the name in slot and return the old one.
+    fn set(&mut self, slot: usize, name: String, new_key: Vec<u8>) -> String {
+        let (_, old) = self.slots[slot].as_mut().expect("indexed slot is empty");
+        let previous = std::mem::replace(old, name);
+        self.unindex(&self.key(&previous), slot);
+        self.paths.entry(new_key).or_default().push(slot);
+        previous
+    }
+
+    // The key of name in the path index: its resolved path, with
+    // separators replaced by SEPARATOR.
+    fn key(&self, name: &str) -> Vec<u8> {
+        let mut key = path::resolve_from(name, self.cwd.as_deref())
+            .into_os_string()
+            .into_vec();
+        for byte in key.iter_mut() {
+            if *byte == b'/' {
+                *byte = SEPARATOR;
+            }
+        }
+        key
+    }
+
+    fn unindex(&mut self, key: &[u8], slot: usize) {
+        if let Some(slots) = self.paths.get_mut(key) {
+            slots.retain(|&s| s != slot);
+            if slots.is_empty() {
+                self.paths.remove(key);
+            }
+        }
+    }
+}
+
+fn unkey(byte: u8) -> u8 {
+    if byte == SEPARATOR {
+        b'/'
+    } else {
+        byte
+    }
+}
+
+// The part of key below dir, empty for dir itself, or None if key is not
+// dir or below it.
+fn below<'a>(key: &'a [u8], dir: &[u8]) -> Option<&'a [u8]> {
+    match key.strip_prefix(dir)? {
+        [] => Some(&[]),
+        [SEPARATOR, rest @ ..] => Some(rest),
+        // The root directory ends with a separator already.
+        rest if dir.last() == Some(&SEPARATOR) => Some(rest),
+        _ => None,
+    }
+}
+
+impl Index<&usize> for ItemTable {
+    type Output = String;
+
+    fn index(&self, num: &usize) -> &String {
+        self.get(num).expect("no item with this number")
+    }
+}
+
+impl Default for ItemTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PartialEq for ItemTable {
+    fn eq(&self, other: &Self) -> bool {
+        self.iter().eq(other.iter())
+    }
+}
+
+impl fmt::Debug for ItemTable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl FromIterator<(usize, String)> for ItemTable {
+    fn from_iter<I: IntoIterator<Item = (usize, String)>>(iter: I) -> Self {
+        let mut table = Self::new();
+        for (num, name) in iter {
+            table.insert(num, name);
+        }
+        table
+    }
+}
+
+impl<const N: usize> From<[(usize, String); N]> for ItemTable {
+    fn from(items: [(usize, String); N]) -> Self {
+        items.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(items: &[(usize, &str)]) -> ItemTable {
+        items
+            .iter()
+            .map(|(num, name)| (*num, name.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_order() {
+        let mut items = table(&[(3, "c"), (1, "a"), (2, "b")]);
+
+        assert_eq!(items.remove(&1).as_deref(), Some("a"));
+        assert_eq!(items.insert(3, "z".to_string()).as_deref(), Some("c"));
+        assert_eq!(items.insert(1, "a".to_string()), None);
+
+        let listed: Vec<(usize, &str)> = items.iter().map(|(n, s)| (n, s.as_str())).collect();
+        assert_eq!(listed, vec![(3, "z"), (2, "b"), (1, "a")]);
+        assert_eq!(items.len(), 3);
+        assert_eq!(items[&3], "z");
+        assert!(!items.contains(&4));
+    }
+
+    #[test]
+    fn test_rename() {
+        let mut items = table(&[(1, "./a"), (2, "b"), (3, "a/x")]);
+
+        items.rename("a/", "b~");
+        assert_eq!(items, table(&[(1, "b~"), (2, "b"), (3, "a/x")]));
+
+        items.rename("./b~", "a");
+        assert_eq!(items.find("./a"), Some(1));
+        items.remove(&2);
+        assert_eq!(items.find("b"), None);
+        items.rename("b", "c");
+        assert_eq!(items, table(&[(1, "a"), (3, "a/x")]));
+    }
+
+    #[test]
+    fn test_overlaps() {
+        let items = table(&[(1, "some/dir"), (2, "./file")]);
+
+        assert!(items.overlaps("some/dir/"));
+        assert!(items.overlaps("some/dir/deep/a"));
+        assert!(items.overlaps("some"));
+        assert!(items.overlaps("file"));
+        assert!(!items.overlaps("some/dir2"));
+        assert!(!items.overlaps("some/di"));
+        assert!(!items.overlaps("file2"));
+
+        assert!(table(&[(1, "/")]).overlaps("/a/b"));
+        assert!(!table(&[(1, "/a")]).overlaps("/ab"));
+    }
+
+    #[test]
+    fn test_rename_dir() {
+        let mut items = table(&[
+            (1, "./some/dir"),
+            (2, "some/dir/a"),
+            (3, "some/dir/deep/b"),
+            (4, "some/dir2/c"),
+            (5, "some/di"),
+            (6, "some/dir-x/d"),
+            (7, "some/dir/.e"),
+        ]);
+
+        items.rename_dir("some/dir/", "other");
+        assert_eq!(
+            items,
+            table(&[
+                (1, "other"),
+                (2, "other/a"),
+                (3, "other/deep/b"),
+                (4, "some/dir2/c"),
+                (5, "some/di"),
+                (6, "some/dir-x/d"),
+                (7, "other/.e"),
+            ])
+        );
+
+        // The index follows the new names.
+        items.rename_dir("other/deep", "x");
+        items.rename("other/a", "y");
+        assert_eq!(items[&2], "y");
+        assert_eq!(items[&3], "x/b");
+    }
+}
@@ -1,9 +1,31 @@
+pub mod config;
 pub mod editor;
+pub mod items;
+pub mod map;
+pub mod meta;
 pub mod ops;
+pub mod path;
+pub mod rename;
+pub mod session;
 
+pub use session::{Plan, Report, Session};
+
+use serde::Deserialize;
 use std::fs;
 use std::io::{self, BufRead, ErrorKind};
 
+/// Order of the entries of a listing.
+#[derive(Clone, Copy, PartialEq, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Sort {
+    /// By name, directories mixed with files.
+    Name,
+    /// By name, directories before files.
+    DirsFirst,
+    /// By name, directories after files.
+    DirsLast,
+}
+
 #[derive(PartialEq, Debug)]
 pub struct Parsed {
     pub files: Vec<String>,
@@ -16,10 +38,43 @@ impl Parsed {
         all.extend(self.dirs.clone());
         all
     }
+
+    /// All entries in the given order.
+    pub fn sorted(&self, sort: Sort) -> Vec<String> {
+        let mut files = self.files.clone();
+        let mut dirs = self.dirs.clone();
+        files.sort();
+        dirs.sort();
+
+        match sort {
+            Sort::Name => {
+                files.extend(dirs);
+                files.sort();
+                files
+            }
+            Sort::DirsFirst => {
+                dirs.extend(files);
+                dirs
+            }
+            Sort::DirsLast => {
+                files.extend(dirs);
+                files
+            }
+        }
+    }
 }
 
 // https://stackoverflow.com/questions/38183551/concisely-initializing-a-vector-of-strings
 pub fn parse_args<F>(args: &[String], read_from: F) -> io::Result<Parsed>
+where
+    F: Fn() -> Box<dyn BufRead>,
+{
+    parse_args_with(args, read_from, true)
+}
+
+/// Like `parse_args`, leaving out names starting with a dot found in
+/// directories unless hidden is true.
+pub fn parse_args_with<F>(args: &[String], read_from: F, hidden: bool) -> io::Result<Parsed>
 where
     F: Fn() -> Box<dyn BufRead>,
 {
@@ -46,16 +101,17 @@ where
                     let entries = fs::read_dir(arg)?;
                     for entry in entries {
                         let entry = entry?;
+                        if !hidden && entry.file_name().as_encoded_bytes().starts_with(b".") {
+                            continue;
+                        }
 
-                        match entry.path().to_str() {
-                            Some(path) => {
-                                if entry.path().is_dir() {
-                                    parsed.dirs.push(path.to_string())
-                                } else {
-                                    parsed.files.push(path.to_string())
-                                }
+                        // TODO: Log paths that are not valid UTF-8.
+                        if let Some(path) = entry.path().to_str() {
+                            if entry.path().is_dir() {
+                                parsed.dirs.push(path.to_string())
+                            } else {
+                                parsed.files.push(path.to_string())
                             }
-                            None => {} // TODO: Log here
                         }
                     }
                 } else {
@@ -78,6 +134,7 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use assert_fs::prelude::*;
     use std::io::{BufReader, Cursor};
 
     #[test]
@@ -139,6 +196,26 @@ mod tests {
         assert_eq!(result.unwrap(), expected);
     }
 
+    #[test]
+    fn test_parse_args_with_hidden() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        temp.child(".hidden").touch().unwrap();
+        temp.child("shown").touch().unwrap();
+        let dir = temp.to_str().unwrap().to_string();
+        let read_from = || -> Box<dyn BufRead> { Box::new(Cursor::new(String::new())) };
+
+        // Hidden names given as arguments are kept.
+        let input = vec![dir.clone(), ".explicit".to_string()];
+        let parsed = parse_args_with(&input, read_from, false).unwrap();
+        assert_eq!(
+            parsed.files,
+            vec![format!("{}/shown", dir), ".explicit".to_string()]
+        );
+
+        let parsed = parse_args_with(&[dir], read_from, true).unwrap();
+        assert_eq!(parsed.files.len(), 2);
+    }
+
     #[test]
     fn test_all() {
         let parsed = Parsed {
@@ -159,4 +236,19 @@ mod tests {
 
         assert_eq!(parsed.all(), expected);
     }
+
+    #[test]
+    fn test_sorted() {
+        let parsed = Parsed {
+            files: vec!["b".to_string(), "a".to_string(), "d".to_string()],
+            dirs: vec!["e".to_string(), "c".to_string()],
+        };
+
+        assert_eq!(parsed.sorted(Sort::Name), vec!["a", "b", "c", "d", "e"]);
+        assert_eq!(
+            parsed.sorted(Sort::DirsFirst),
+            vec!["c", "e", "a", "b", "d"]
+        );
+        assert_eq!(parsed.sorted(Sort::DirsLast), vec!["a", "b", "d", "c", "e"]);
+    }
 }
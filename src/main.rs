@@ -1,64 +1,490 @@
-use clap::Parser;
-use std::fs::File;
-use std::io::Write;
-use std::io::{self, BufRead};
-use std::process::Command;
+use anyhow::{bail, Result};
+use clap::{Parser, ValueEnum};
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
 use tempfile::NamedTempFile;
-use vidirr::ops;
+use vidirr::config::Config;
+use vidirr::editor::{Format, Ids};
+use vidirr::rename::{Substitution, Template, Transform};
+use vidirr::{map, ops, path, session, Plan, Session, Sort};
 
 #[derive(Parser)]
 struct Cli {
+    /// Sort the listing by name, with directories mixed with files unless
+    /// told otherwise with --sort=ORDER
+    #[arg(
+        short,
+        long,
+        value_name = "ORDER",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "name"
+    )]
+    sort: Option<SortOrder>,
+    /// Print every change made to the file system
+    #[arg(short, long, overrides_with = "no_verbose")]
+    verbose: bool,
+    /// Only print errors, even if the configuration asks for --verbose
+    #[arg(long)]
+    no_verbose: bool,
+    /// List names starting with a dot, the default unless the
+    /// configuration hides them
+    #[arg(long, overrides_with = "no_hidden")]
+    hidden: bool,
+    /// Leave out names starting with a dot found in directories
+    #[arg(long)]
+    no_hidden: bool,
+    /// Show symlink targets as `name -> target` and allow editing them
+    #[arg(long)]
+    links: bool,
+    /// Show file type, permissions, owner, size and modification time;
+    /// permissions and owner can be edited
     #[arg(short, long)]
-    sort: bool,
+    long: bool,
+    /// Show the modification time and allow editing it
     #[arg(short, long)]
-    verbose: bool,
+    mtime: bool,
+    /// List paths relative to the current directory
+    #[arg(long, conflicts_with = "absolute")]
+    relative: bool,
+    /// List absolute paths
+    #[arg(long)]
+    absolute: bool,
+    /// Number items by position in the listing, or by inode number, which
+    /// stays the same when a file is renamed or listed again
+    #[arg(long, value_name = "KIND", default_value = "position")]
+    ids: IdKind,
+    /// Show names relative to the directory containing all of them
+    #[arg(long)]
+    strip_prefix: bool,
+    /// Add comments explaining the syntax to the file list
+    #[arg(long)]
+    header: bool,
+    /// Print the file list instead of opening an editor and save its ID map
+    /// for --apply
+    #[arg(long, conflicts_with = "apply")]
+    dump: bool,
+    /// Apply a file list printed by --dump and edited since, - reads it from
+    /// stdin
+    #[arg(long, value_name = "FILE")]
+    apply: Option<String>,
+    /// Where --dump saves and --apply reads the ID map
+    #[arg(long, value_name = "FILE", default_value = ".vidirr.map")]
+    map: String,
+    /// Pipe the file list through a shell command instead of opening an
+    /// editor
+    #[arg(long, value_name = "COMMAND", conflicts_with_all = ["dump", "apply"])]
+    filter: Option<String>,
+    /// Rename with a Perl style substitution such as 's/IMG_(\d+)/photo-$1/'
+    /// of the last component of each name instead of opening an editor; can
+    /// be given more than once
+    #[arg(long, value_name = "EXPR", conflicts_with_all = ["dump", "apply", "filter"])]
+    rename: Vec<String>,
+    /// Name entries after a template such as 'holiday-{n:03}{ext}' in listing
+    /// order, where {n:03} is the position padded to 3 digits; other
+    /// placeholders are {stem}, {ext}, {parent}, {date} and {size}
+    #[arg(long, value_name = "TEMPLATE", conflicts_with_all = ["dump", "apply", "filter"])]
+    template: Option<String>,
+    /// Lowercase names
+    #[arg(long, conflicts_with_all = ["upper", "dump", "apply", "filter"])]
+    lower: bool,
+    /// Uppercase names
+    #[arg(long, conflicts_with_all = ["dump", "apply", "filter"])]
+    upper: bool,
+    /// Turn names into lowercase ASCII words separated by dashes
+    #[arg(long, conflicts_with_all = ["dump", "apply", "filter"])]
+    slugify: bool,
+    /// Transliterate names to ASCII
+    #[arg(long, conflicts_with_all = ["dump", "apply", "filter"])]
+    ascii: bool,
+    /// Normalize names to Unicode NFC
+    #[arg(long, conflicts_with_all = ["dump", "apply", "filter"])]
+    unicode_nfc: bool,
+    /// Review the changes and ask before applying them
+    #[arg(long, overrides_with = "no_confirm")]
+    confirm: bool,
+    /// Apply the changes without asking, even if the configuration asks
+    /// for --confirm
+    #[arg(long)]
+    no_confirm: bool,
+    /// Abort without changing anything if a file to rename, copy or update
+    /// was changed, moved or deleted by another process since it was
+    /// listed, instead of only skipping that file
+    #[arg(long)]
+    abort_on_drift: bool,
+    /// Print the changes without applying them
+    #[arg(short = 'n', long)]
+    dry_run: bool,
+    /// Copy up to N files at once; renames and other changes still run
+    /// one at a time
+    #[arg(short, long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    jobs: u16,
+    /// Print what happened to each item as a JSON array, or as JSON Lines
+    /// with one object per item
+    #[arg(long, value_name = "FORMAT")]
+    report: Option<ReportFormat>,
+    /// Write the --report to a file instead of stdout
+    #[arg(long, value_name = "FILE", requires = "report")]
+    report_file: Option<String>,
     files: Vec<String>,
 }
 
+#[derive(Clone, Copy, ValueEnum)]
+enum IdKind {
+    Position,
+    Inode,
+}
+
+impl From<IdKind> for Ids {
+    fn from(kind: IdKind) -> Self {
+        match kind {
+            IdKind::Position => Ids::Position,
+            IdKind::Inode => Ids::Inode,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ReportFormat {
+    Json,
+    Jsonl,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum SortOrder {
+    Name,
+    DirsFirst,
+    DirsLast,
+}
+
+impl From<SortOrder> for Sort {
+    fn from(order: SortOrder) -> Self {
+        match order {
+            SortOrder::Name => Sort::Name,
+            SortOrder::DirsFirst => Sort::DirsFirst,
+            SortOrder::DirsLast => Sort::DirsLast,
+        }
+    }
+}
+
 fn main() {
     let args = Cli::parse();
-    let mut files = args.files;
+
+    if let Err(err) = run(args) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+fn run(mut args: Cli) -> Result<()> {
+    // Options given on the command line win over the configuration.
+    let config = Config::load(&config_dir(&args.files))?;
+    args.verbose = flag(args.verbose, args.no_verbose, config.verbose);
+    args.confirm = flag(args.confirm, args.no_confirm, config.confirm);
+    let hidden = flag(args.hidden, args.no_hidden, config.hidden.or(Some(true)));
+    let sort = args.sort.map(Sort::from).or(config.sort);
+    let editor = editor(&config);
+
+    let mut files = args.files.clone();
+    let mut format = Format {
+        links: args.links,
+        long: args.long,
+        mtime: args.mtime,
+        header: args.header,
+        ids: args.ids.into(),
+        base: None,
+    };
+
+    // The format of the list is taken from the map so that it is parsed
+    // the same way it was dumped.
+    if let Some(list) = &args.apply {
+        let map = map::read(BufReader::new(File::open(&args.map)?))?;
+        let reader: Box<dyn BufRead> = if list == "-" {
+            Box::new(io::stdin().lock())
+        } else {
+            Box::new(BufReader::new(File::open(list)?))
+        };
+        let parsed_lines = session::parse(reader, &map.format)?;
+        let mut operator = ops::Operator::new(map.items)
+            .snapshots(map.snapshots)
+            .verbose(args.verbose)
+            .verbose_to_stderr(report_to_stdout(&args))
+            .jobs(args.jobs.into());
+        if let Some(base) = &map.format.base {
+            operator = operator.base(base);
+        }
+        return apply(Plan::new(operator, parsed_lines), &args);
+    }
 
     if files.is_empty() {
         files.push("./".to_string())
     }
 
-    let target = vidirr::parse_args(&files, || Box::new(io::stdin().lock())).expect("cannot parse"); // TODO: Handle error
+    let target = vidirr::parse_args_with(&files, || Box::new(io::stdin().lock()), hidden)?;
+    let mut sources = match sort {
+        Some(sort) => target.sorted(sort),
+        None => target.all(),
+    };
+    if args.relative || args.absolute {
+        sources = sources
+            .iter()
+            .map(|source| {
+                let display = if args.relative {
+                    path::relative(source)?
+                } else {
+                    path::absolute(source)?
+                };
+                Ok(display.to_string_lossy().into_owned())
+            })
+            .collect::<io::Result<_>>()?;
+    }
+    if args.strip_prefix {
+        format.base = path::common_dir(&sources);
+    }
+
+    if args.dump {
+        let items = vidirr::editor::write_with_format(&mut io::stdout(), &sources, &format)?;
+        map::write(&mut File::create(&args.map)?, &items, &format)?;
+        return Ok(());
+    }
+
+    let session = Session::new(sources.clone())
+        .format(format)
+        .verbose(args.verbose)
+        .verbose_to_stderr(report_to_stdout(&args))
+        .jobs(args.jobs.into());
+
+    if let Some(command) = &args.filter {
+        let plan = session.edit(|list| {
+            let output = vidirr::editor::filter(command, list.into_bytes())?;
+            Ok(String::from_utf8(output)?)
+        })?;
+        return apply(plan, &args);
+    }
+
+    let transforms = transforms(&args);
+    if args.template.is_some() || !args.rename.is_empty() || !transforms.is_empty() {
+        let template = args.template.as_deref().map(Template::parse).transpose()?;
+        let substitutions = args
+            .rename
+            .iter()
+            .map(|expr| Substitution::parse(expr))
+            .collect::<Result<Vec<_>>>()?;
+
+        let items = vidirr::editor::write_with_ids(&mut io::sink(), &sources)?;
+        let snapshots = ops::snapshot(&items);
+        let mut n = 0;
+        let parsed_lines = vidirr::rename::rename_with(&items, |name| {
+            n += 1;
+            let name = match &template {
+                Some(template) => template.render(n, name),
+                None => name.to_string(),
+            };
+            let name = substitutions
+                .iter()
+                .fold(name, |name, sub| sub.apply(&name));
+            transforms.iter().fold(name, |name, t| t.apply(&name))
+        });
+        if let Err(err) = vidirr::rename::check_names(&items, &parsed_lines) {
+            eprintln!("{}", err);
+            bail!("conflicting names, aborting");
+        }
+        let operator = ops::Operator::new(items)
+            .snapshots(snapshots)
+            .verbose(args.verbose)
+            .verbose_to_stderr(report_to_stdout(&args))
+            .jobs(args.jobs.into());
+        return apply(Plan::new(operator, parsed_lines), &args);
+    }
+
+    let file_list = NamedTempFile::new()?;
 
-    let mut file_list = NamedTempFile::new().expect("cannot create temp file"); // TODO: Handle error
+    // Invalid lists are handed back to the editor so that mistakes can be
+    // fixed without losing the other edits.
+    // The error has been shown by the time the user declines, so only
+    // the abort is reported then.
+    let session = session.retry(|err| {
+        eprintln!("{}", err);
+        if !matches!(
+            ask("edit the file list again? [Y/n] ")?.as_deref(),
+            Some("" | "y" | "Y" | "yes")
+        ) {
+            bail!("aborting");
+        }
+        Ok(true)
+    });
+    let plan = session.edit(|list| {
+        fs::write(file_list.path(), list)?;
+
+        // The editor may be a command with arguments, the file list is
+        // passed as an argument of the shell to avoid quoting it.
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(format!("{} \"$@\"", editor))
+            .arg("sh")
+            .arg(file_list.path())
+            .status()?;
+        if !status.success() {
+            bail!("{} exited nonzero, aborting", editor);
+        }
+
+        Ok(fs::read_to_string(file_list.path())?)
+    })?;
+    apply(plan, &args)
+}
+
+// The directory whose project configuration applies: the first directory
+// given, or the one containing the first file, or the current directory.
+fn config_dir(files: &[String]) -> PathBuf {
+    match files.iter().find(|file| *file != "-") {
+        Some(file) if Path::new(file).is_dir() => PathBuf::from(file),
+        Some(file) => match Path::new(file).parent() {
+            Some(parent) if parent != Path::new("") => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        },
+        None => PathBuf::from("."),
+    }
+}
+
+// A boolean option turned on or off on the command line, or else by the
+// configuration.
+fn flag(on: bool, off: bool, config: Option<bool>) -> bool {
+    on || (!off && config.unwrap_or(false))
+}
 
-    let items =
-        vidirr::editor::write_with_ids(&mut file_list, &target.all()).expect("cannot write");
+// The editor set in the configuration, $VISUAL, $EDITOR, or vi. Like
+// core.editor of git, the setting of vidirr wins over the variables.
+fn editor(config: &Config) -> String {
+    config
+        .editor
+        .clone()
+        .into_iter()
+        .chain(
+            ["VISUAL", "EDITOR"]
+                .into_iter()
+                .filter_map(|var| env::var(var).ok()),
+        )
+        .find(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_string())
+}
 
-    println!("{:?}", file_list.path()); // TODO: Remove this.
+// Transforms given on the command line, in the order they are applied.
+fn transforms(args: &Cli) -> Vec<Transform> {
+    [
+        (args.unicode_nfc, Transform::UnicodeNfc),
+        (args.ascii, Transform::Ascii),
+        (args.slugify, Transform::Slugify),
+        (args.lower, Transform::Lower),
+        (args.upper, Transform::Upper),
+    ]
+    .into_iter()
+    .filter_map(|(enabled, transform)| enabled.then_some(transform))
+    .collect()
+}
 
-    Command::new("vi")
-        .arg(file_list.path().to_string_lossy().to_string())
-        .status()
-        .expect("Failed to execute command"); // TODO: Handle error
-                                              //
-    let reader = io::BufReader::new(File::open(file_list.path()).expect("cannot open file"));
+fn apply(plan: Plan, args: &Cli) -> Result<()> {
+    if args.dry_run {
+        for change in plan.preview() {
+            println!("{}", change);
+        }
+        return Ok(());
+    }
 
-    let mut operator = ops::Operator::new(items);
-    for line in reader.lines() {
-        let l = line.expect("cannot read line"); // TODO: Handle error
+    if args.confirm && !confirm(&plan.preview(), console(args))? {
+        return Ok(());
+    }
 
-        let parsed_line = vidirr::editor::parse_line(&l).expect("cannot parse line");
-        //    die "$0: unable to parse line \"$_\", aborting\n";
+    let report = plan.abort_on_drift(args.abort_on_drift).apply()?;
+    for (_, err) in &report.failed {
+        eprintln!("{}", err);
+    }
 
-        match parsed_line {
-            Some(parsed_line) => match operator.apply_changes(parsed_line, ops::FS) {
-                Ok(_) => {}
-                Err(err) => {
-                    if let Some(e) = err.downcast_ref::<ops::OpsError>() {
-                        println!("{}", e)
-                    } else {
-                        panic!("ahhhhhhh")
-                    }
-                }
-            },
-            None => continue, // Skip empty line.
+    if let Some(format) = args.report {
+        let writer: Box<dyn Write> = match &args.report_file {
+            Some(file) => Box::new(File::create(file)?),
+            None => Box::new(io::stdout().lock()),
+        };
+        match format {
+            ReportFormat::Json => report.write_json(writer)?,
+            ReportFormat::Jsonl => report.write_jsonl(writer)?,
         }
     }
-    // Remove
+
+    // The errors have been printed, only the exit status is left.
+    if !report.is_success() {
+        bail!(
+            "{} of {} lines failed",
+            report.failed.len(),
+            report.applied.len() + report.failed.len()
+        );
+    }
+    Ok(())
+}
+
+// Whether the report is printed on stdout, which then holds nothing else.
+fn report_to_stdout(args: &Cli) -> bool {
+    args.report.is_some() && args.report_file.is_none()
+}
+
+// Where messages other than errors go: stdout, unless it holds the report.
+fn console(args: &Cli) -> Box<dyn Write> {
+    if report_to_stdout(args) {
+        Box::new(io::stderr())
+    } else {
+        Box::new(io::stdout())
+    }
+}
+
+// Show changes and ask whether to apply them.
+fn confirm(changes: &[String], mut console: Box<dyn Write>) -> Result<bool> {
+    if changes.is_empty() {
+        writeln!(console, "nothing to change")?;
+        return Ok(false);
+    }
+    for change in changes {
+        writeln!(console, "{}", change)?;
+    }
+    Ok(matches!(
+        ask("apply these changes? [y/N] ")?.as_deref(),
+        Some("y" | "Y" | "yes")
+    ))
+}
+
+// Ask a question and return the trimmed answer, or None at end of input.
+// The answer is read from the terminal because stdin may hold the file
+// list, and the question is printed on stderr because stdout may hold a
+// report.
+fn ask(question: &str) -> Result<Option<String>> {
+    eprint!("{}", question);
+    io::stderr().flush()?;
+
+    let mut answer = String::new();
+    let read = match File::open("/dev/tty") {
+        Ok(tty) => BufReader::new(tty).read_line(&mut answer)?,
+        Err(_) => io::stdin().read_line(&mut answer)?,
+    };
+    Ok((read > 0).then(|| answer.trim().to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cli_sort() {
+        let args = Cli::try_parse_from(["vidirr", "-s", "somedir"]).unwrap();
+        assert!(matches!(args.sort, Some(SortOrder::Name)));
+        assert_eq!(args.files, vec!["somedir"]);
+
+        let args = Cli::try_parse_from(["vidirr", "--sort=dirs-first", "somedir"]).unwrap();
+        assert!(matches!(args.sort, Some(SortOrder::DirsFirst)));
+        assert_eq!(args.files, vec!["somedir"]);
+
+        let args = Cli::try_parse_from(["vidirr", "somedir"]).unwrap();
+        assert!(args.sort.is_none());
+        assert!(Cli::try_parse_from(["vidirr", "--sort=size"]).is_err());
+    }
 }
@@ -0,0 +1,203 @@
+use crate::editor::{Format, Ids};
+use crate::items::ItemTable;
+use crate::meta;
+use crate::ops::Snapshot;
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+
+// The map is a file list preceded by a comment recording the format of the
+// dumped list, so that it is parsed the same way when the edited list is
+// applied. Each line holds the number, snapshot and name of an item.
+const MAGIC: &str = "# vidirr map:";
+
+// Marks the base directory of names, which comes last on the first line
+// because it may contain spaces.
+const BASE: &str = "base=";
+
+/// The items of a dumped file list.
+#[derive(Debug)]
+pub struct Map {
+    pub items: ItemTable,
+    /// Snapshots of the items that existed when the list was dumped.
+    pub snapshots: HashMap<usize, Snapshot>,
+    pub format: Format,
+}
+
+/// Write the ID map of a dumped file list.
+pub fn write<W: Write>(output: &mut W, items: &ItemTable, format: &Format) -> std::io::Result<()> {
+    let mut flags = Vec::new();
+    if format.links {
+        flags.push("links");
+    }
+    if format.long {
+        flags.push("long");
+    }
+    if format.mtime {
+        flags.push("mtime");
+    }
+    if format.ids == Ids::Inode {
+        flags.push("inode");
+    }
+    let base = format
+        .base
+        .as_ref()
+        .map(|base| format!("{}{}", BASE, base.display()));
+    flags.extend(base.as_deref());
+    writeln!(output, "{} {}", MAGIC, flags.join(" "))?;
+
+    for (num, name) in items.iter() {
+        let snapshot = match Snapshot::of(name) {
+            Some(snapshot) => snapshot.to_string(),
+            None => meta::UNKNOWN.to_string(),
+        };
+        writeln!(output, "{} {} {}", num, snapshot, name)?;
+    }
+    Ok(())
+}
+
+/// Read an ID map written by `write`.
+pub fn read<R: BufRead>(mut input: R) -> Result<Map> {
+    let mut first = String::new();
+    input.read_line(&mut first)?;
+
+    let flags = first
+        .trim_end_matches('\n')
+        .strip_prefix(MAGIC)
+        .ok_or_else(|| anyhow!("not a vidirr map"))?;
+
+    let mut format = Format::default();
+    let flags = match flags.split_once(BASE) {
+        Some((flags, base)) => {
+            format.base = Some(base.into());
+            flags
+        }
+        None => flags,
+    };
+    for flag in flags.split_whitespace() {
+        match flag {
+            "links" => format.links = true,
+            "long" => format.long = true,
+            "mtime" => format.mtime = true,
+            "inode" => format.ids = Ids::Inode,
+            _ => return Err(anyhow!("unknown format {:?} in map", flag)),
+        }
+    }
+
+    let mut items = ItemTable::new();
+    let mut snapshots = HashMap::new();
+    for line in input.lines() {
+        let line = line?;
+        let (num, snapshot, name) = line
+            .split_once(' ')
+            .and_then(|(num, rest)| Some((num, rest.split_once(' ')?)))
+            .map(|(num, (snapshot, name))| (num, snapshot, name))
+            .ok_or_else(|| anyhow!("invalid line in map: {:?}", line))?;
+
+        let num = num.parse().map_err(|_| anyhow!("missing number in map"))?;
+        if snapshot != meta::UNKNOWN {
+            snapshots.insert(num, snapshot.parse()?);
+        }
+        items.insert(num, name.to_string());
+    }
+    Ok(Map {
+        items,
+        snapshots,
+        format,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_write_read() {
+        let items = ItemTable::from([
+            (1, "./src/testdata/file2".to_string()),
+            (2, "./src/testdata/file1".to_string()),
+            (3, "#xyz".to_string()),
+        ]);
+        let format = Format {
+            long: true,
+            links: true,
+            ..Default::default()
+        };
+
+        let mut buffer = Vec::new();
+        write(&mut buffer, &items, &format).unwrap();
+
+        let snapshot_1 = Snapshot::of("./src/testdata/file1").unwrap();
+        let snapshot_2 = Snapshot::of("./src/testdata/file2").unwrap();
+        assert_eq!(
+            String::from_utf8(buffer.clone()).unwrap(),
+            format!(
+                "# vidirr map: links long
+1 {} ./src/testdata/file2
+2 {} ./src/testdata/file1
+3 ? #xyz
+",
+                snapshot_2, snapshot_1
+            )
+        );
+
+        let map = read(Cursor::new(buffer)).unwrap();
+        assert_eq!(map.items, items);
+        assert_eq!(
+            map.snapshots,
+            HashMap::from([(1, snapshot_2), (2, snapshot_1)])
+        );
+        assert!(map.format.long);
+        assert!(map.format.links);
+        assert!(!map.format.mtime);
+        assert_eq!(map.format.ids, Ids::Position);
+        assert!(map.format.base.is_none());
+    }
+
+    #[test]
+    fn test_write_read_base() {
+        let items = ItemTable::from([(1, "some dir/file 1".to_string())]);
+        let format = Format {
+            mtime: true,
+            ids: Ids::Inode,
+            base: Some("some dir".into()),
+            ..Default::default()
+        };
+
+        let mut buffer = Vec::new();
+        write(&mut buffer, &items, &format).unwrap();
+
+        assert_eq!(
+            String::from_utf8(buffer.clone()).unwrap(),
+            "# vidirr map: mtime inode base=some dir
+1 ? some dir/file 1
+"
+        );
+
+        let map = read(Cursor::new(buffer)).unwrap();
+        assert_eq!(map.items, items);
+        assert!(map.snapshots.is_empty());
+        assert!(map.format.mtime);
+        assert_eq!(map.format.ids, Ids::Inode);
+        assert_eq!(map.format.base, Some("some dir".into()));
+    }
+
+    #[test]
+    fn test_read_not_a_map() {
+        let res = read(Cursor::new("1 file\n"));
+        assert_eq!(res.unwrap_err().to_string(), "not a vidirr map");
+    }
+
+    #[test]
+    fn test_read_invalid_line() {
+        let res = read(Cursor::new("# vidirr map: \n1 file\n"));
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            "invalid line in map: \"1 file\""
+        );
+
+        let res = read(Cursor::new("# vidirr map: \n1 2049 file\n"));
+        assert_eq!(res.unwrap_err().to_string(), "invalid snapshot \"2049\"");
+    }
+}
@@ -0,0 +1,300 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone};
+use nix::unistd::{Gid, Group, Uid, User};
+use std::fs::{FileType, Metadata};
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+use std::time::SystemTime;
+
+/// Placeholder rendered for columns whose value is unknown, e.g. because
+/// the file does not exist.
+pub const UNKNOWN: &str = "?";
+
+const MTIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+
+// Single character describing the file type, as in the first column of
+// `ls -l`.
+pub fn format_type(file_type: &FileType) -> char {
+    if file_type.is_dir() {
+        'd'
+    } else if file_type.is_symlink() {
+        'l'
+    } else if file_type.is_fifo() {
+        'p'
+    } else if file_type.is_socket() {
+        's'
+    } else if file_type.is_block_device() {
+        'b'
+    } else if file_type.is_char_device() {
+        'c'
+    } else {
+        '-'
+    }
+}
+
+// Permission bits in the `rwxr-xr-x` form used by `ls -l`, including the
+// setuid, setgid and sticky bits.
+pub fn format_mode(mode: u32) -> String {
+    let special = [(0o4000, 's', 'S'), (0o2000, 's', 'S'), (0o1000, 't', 'T')];
+    let mut out = String::with_capacity(9);
+
+    for (i, (bit, set_exec, set_no_exec)) in special.iter().enumerate() {
+        let shift = 6 - i * 3;
+        let r = mode >> shift & 0o4 != 0;
+        let w = mode >> shift & 0o2 != 0;
+        let x = mode >> shift & 0o1 != 0;
+
+        out.push(if r { 'r' } else { '-' });
+        out.push(if w { 'w' } else { '-' });
+        out.push(match (mode & bit != 0, x) {
+            (true, true) => *set_exec,
+            (true, false) => *set_no_exec,
+            (false, true) => 'x',
+            (false, false) => '-',
+        });
+    }
+    out
+}
+
+// Parse permission bits written either in the form produced by
+// format_mode or as an octal number such as 644 or 0644.
+pub fn parse_mode(input: &str) -> Result<Option<u32>> {
+    if input == UNKNOWN {
+        return Ok(None);
+    }
+
+    if input.chars().all(|c| c.is_ascii_digit()) {
+        return match u32::from_str_radix(input, 8) {
+            Ok(mode) if mode <= 0o7777 => Ok(Some(mode)),
+            _ => Err(anyhow!("invalid mode {:?}", input)),
+        };
+    }
+
+    let chars: Vec<char> = input.chars().collect();
+    if chars.len() != 9 {
+        return Err(anyhow!("invalid mode {:?}", input));
+    }
+
+    let special = [(0o4000, 's', 'S'), (0o2000, 's', 'S'), (0o1000, 't', 'T')];
+    let mut mode = 0;
+
+    for (i, (bit, set_exec, set_no_exec)) in special.iter().enumerate() {
+        let shift = 6 - i * 3;
+        let triple = &chars[i * 3..i * 3 + 3];
+
+        match triple[0] {
+            'r' => mode |= 0o4 << shift,
+            '-' => {}
+            _ => return Err(anyhow!("invalid mode {:?}", input)),
+        }
+        match triple[1] {
+            'w' => mode |= 0o2 << shift,
+            '-' => {}
+            _ => return Err(anyhow!("invalid mode {:?}", input)),
+        }
+        match triple[2] {
+            'x' => mode |= 0o1 << shift,
+            '-' => {}
+            c if c == *set_exec => mode |= 0o1 << shift | bit,
+            c if c == *set_no_exec => mode |= bit,
+            _ => return Err(anyhow!("invalid mode {:?}", input)),
+        }
+    }
+    Ok(Some(mode))
+}
+
+// Owner in the `user:group` form, falling back to numeric ids when they
+// have no name.
+pub fn format_owner(uid: u32, gid: u32) -> String {
+    let user = match User::from_uid(Uid::from_raw(uid)) {
+        Ok(Some(user)) => user.name,
+        _ => uid.to_string(),
+    };
+    let group = match Group::from_gid(Gid::from_raw(gid)) {
+        Ok(Some(group)) => group.name,
+        _ => gid.to_string(),
+    };
+    format!("{}:{}", user, group)
+}
+
+// Parse `user:group`, `user` or `:group` into uid and gid. Both names and
+// numeric ids are accepted.
+pub fn parse_owner(input: &str) -> Result<(Option<u32>, Option<u32>)> {
+    if input == UNKNOWN {
+        return Ok((None, None));
+    }
+
+    let (user, group) = match input.split_once(':') {
+        Some((user, group)) => (user, group),
+        None => (input, ""),
+    };
+
+    let uid = match user {
+        "" => None,
+        user => Some(match user.parse::<u32>() {
+            Ok(uid) => uid,
+            Err(_) => match User::from_name(user)? {
+                Some(user) => user.uid.as_raw(),
+                None => return Err(anyhow!("unknown user {:?}", user)),
+            },
+        }),
+    };
+    let gid = match group {
+        "" => None,
+        group => Some(match group.parse::<u32>() {
+            Ok(gid) => gid,
+            Err(_) => match Group::from_name(group)? {
+                Some(group) => group.gid.as_raw(),
+                None => return Err(anyhow!("unknown group {:?}", group)),
+            },
+        }),
+    };
+    Ok((uid, gid))
+}
+
+pub fn format_mtime(mtime: SystemTime) -> String {
+    DateTime::<Local>::from(mtime)
+        .format(MTIME_FORMAT)
+        .to_string()
+}
+
+// Parse a modification time in ISO 8601. Times without an offset, and
+// dates without a time, are taken to be in the local time zone.
+pub fn parse_mtime(input: &str) -> Result<Option<SystemTime>> {
+    if input == UNKNOWN {
+        return Ok(None);
+    }
+
+    if let Ok(time) = DateTime::parse_from_rfc3339(input) {
+        return Ok(Some(time.into()));
+    }
+
+    let naive = NaiveDateTime::parse_from_str(input, "%Y-%m-%dT%H:%M:%S%.f")
+        .or_else(|_| NaiveDateTime::parse_from_str(input, "%Y-%m-%dT%H:%M"))
+        .or_else(|_| {
+            NaiveDate::parse_from_str(input, "%Y-%m-%d")
+                .map(|date| date.and_hms_opt(0, 0, 0).unwrap_or_default())
+        })
+        .map_err(|_| anyhow!("invalid time {:?}", input))?;
+
+    match Local.from_local_datetime(&naive).earliest() {
+        Some(time) => Ok(Some(time.into())),
+        None => Err(anyhow!("invalid time {:?}", input)),
+    }
+}
+
+/// Columns shown in front of the name in the long format. Only mode,
+/// owner and modification time can be edited.
+pub struct Columns {
+    pub file_type: String,
+    pub mode: String,
+    pub owner: String,
+    pub size: String,
+    pub mtime: String,
+}
+
+impl Columns {
+    pub fn new(metadata: Option<&Metadata>) -> Self {
+        match metadata {
+            Some(metadata) => Self {
+                file_type: format_type(&metadata.file_type()).to_string(),
+                mode: format_mode(metadata.permissions().mode()),
+                owner: format_owner(metadata.uid(), metadata.gid()),
+                size: metadata.len().to_string(),
+                mtime: metadata
+                    .modified()
+                    .map(format_mtime)
+                    .unwrap_or_else(|_| UNKNOWN.to_string()),
+            },
+            None => Self {
+                file_type: UNKNOWN.to_string(),
+                mode: UNKNOWN.to_string(),
+                owner: UNKNOWN.to_string(),
+                size: UNKNOWN.to_string(),
+                mtime: UNKNOWN.to_string(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_format_mode() {
+        assert_eq!(format_mode(0o644), "rw-r--r--");
+        assert_eq!(format_mode(0o755), "rwxr-xr-x");
+        assert_eq!(format_mode(0o4755), "rwsr-xr-x");
+        assert_eq!(format_mode(0o2745), "rwxr-Sr-x");
+        assert_eq!(format_mode(0o1777), "rwxrwxrwt");
+        assert_eq!(format_mode(0o1776), "rwxrwxrwT");
+    }
+
+    #[test]
+    fn test_parse_mode() {
+        assert_eq!(parse_mode("rw-r--r--").unwrap(), Some(0o644));
+        assert_eq!(parse_mode("rwsr-xr-x").unwrap(), Some(0o4755));
+        assert_eq!(parse_mode("rwxrwxrwT").unwrap(), Some(0o1776));
+        assert_eq!(parse_mode("0644").unwrap(), Some(0o644));
+        assert_eq!(parse_mode("755").unwrap(), Some(0o755));
+        assert_eq!(parse_mode("?").unwrap(), None);
+
+        assert_eq!(
+            parse_mode("rw-r--r-").unwrap_err().to_string(),
+            "invalid mode \"rw-r--r-\""
+        );
+        assert_eq!(
+            parse_mode("rwxr-xr-q").unwrap_err().to_string(),
+            "invalid mode \"rwxr-xr-q\""
+        );
+        assert_eq!(
+            parse_mode("0800").unwrap_err().to_string(),
+            "invalid mode \"0800\""
+        );
+    }
+
+    #[test]
+    fn test_parse_owner() {
+        assert_eq!(parse_owner("0:0").unwrap(), (Some(0), Some(0)));
+        assert_eq!(parse_owner("root").unwrap(), (Some(0), None));
+        assert_eq!(parse_owner("1000").unwrap(), (Some(1000), None));
+        assert_eq!(parse_owner(":1000").unwrap(), (None, Some(1000)));
+        assert_eq!(parse_owner("?").unwrap(), (None, None));
+
+        assert_eq!(
+            parse_owner("no-such-user-xyz:0").unwrap_err().to_string(),
+            "unknown user \"no-such-user-xyz\""
+        );
+    }
+
+    #[test]
+    fn test_format_owner() {
+        let owner = format_owner(0, 0);
+        assert!(owner.starts_with("root:"));
+        assert_eq!(parse_owner(&owner).unwrap(), (Some(0), Some(0)));
+    }
+
+    #[test]
+    fn test_parse_mtime() {
+        let epoch = SystemTime::UNIX_EPOCH;
+        assert_eq!(
+            parse_mtime("2023-05-01T10:00:00Z").unwrap(),
+            Some(epoch + Duration::from_secs(1682935200))
+        );
+        assert_eq!(
+            parse_mtime("2023-05-01T12:00:00+02:00").unwrap(),
+            Some(epoch + Duration::from_secs(1682935200))
+        );
+        assert_eq!(parse_mtime("?").unwrap(), None);
+
+        // Round trip through the local time zone.
+        let time = epoch + Duration::from_secs(1682935200);
+        assert_eq!(parse_mtime(&format_mtime(time)).unwrap(), Some(time));
+
+        assert_eq!(
+            parse_mtime("2023-02-30").unwrap_err().to_string(),
+            "invalid time \"2023-02-30\""
+        );
+    }
+}
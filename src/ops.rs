@@ -1,8 +1,21 @@
 use crate::editor::ParsedLine;
+use crate::items::ItemTable;
+use crate::{meta, path};
 use anyhow::{bail, Result};
-use std::collections::HashMap;
-use std::fs;
-use std::path::Path;
+use nix::sys::stat::{utimensat, UtimensatFlags};
+use nix::sys::time::TimeSpec;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs::{self, Metadata};
+use std::os::unix::fs::{lchown, symlink, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -25,6 +38,187 @@ pub enum OpsError {
         from: String,
         to: String,
     },
+
+    #[error("cannot move {from:?} into itself as {to:?}")]
+    IntoItself { from: String, to: String },
+
+    #[error("{0} already exists")]
+    AlreadyExists(String),
+
+    #[error("failed to create {name:?}: {source:?}!")]
+    FailCreate {
+        #[source]
+        source: std::io::Error,
+        name: String,
+    },
+
+    #[error("{0} is not a symlink")]
+    NotSymlink(String),
+
+    #[error("{0} is not a directory")]
+    NotDir(String),
+
+    #[error("{name} is no longer the file listed as {num}")]
+    StaleId { num: usize, name: String },
+
+    #[error("{0} changed since it was listed")]
+    Drift(String),
+
+    #[error("failed to point {link:?} to {target:?}: {source:?}!")]
+    FailRelink {
+        #[source]
+        source: std::io::Error,
+        link: String,
+        target: String,
+    },
+
+    #[error("failed to change mode of {name:?} to {mode:o}: {source:?}!")]
+    FailChmod {
+        #[source]
+        source: std::io::Error,
+        name: String,
+        mode: u32,
+    },
+
+    #[error("failed to change owner of {name:?}: {source:?}!")]
+    FailChown {
+        #[source]
+        source: std::io::Error,
+        name: String,
+    },
+
+    #[error("failed to change modification time of {name:?}: {source:?}!")]
+    FailTouch {
+        #[source]
+        source: std::io::Error,
+        name: String,
+    },
+}
+
+/// The device and inode of a listed file, which tell whether its name
+/// still refers to it when the changes are applied.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Identity {
+    pub dev: u64,
+    pub ino: u64,
+}
+
+impl fmt::Display for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.dev, self.ino)
+    }
+}
+
+impl FromStr for Identity {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || anyhow::anyhow!("invalid identity {:?}", s);
+        let (dev, ino) = s.split_once(':').ok_or_else(invalid)?;
+        Ok(Self {
+            dev: dev.parse().map_err(|_| invalid())?,
+            ino: ino.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// The identity, size and modification time of a listed file, taken to
+/// detect changes made by other processes before the edits are applied.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Snapshot {
+    pub identity: Identity,
+    pub size: u64,
+    /// Seconds and nanoseconds since the epoch.
+    pub mtime: (i64, i64),
+}
+
+impl Snapshot {
+    pub fn of(name: &str) -> Option<Self> {
+        let metadata = fs::symlink_metadata(name).ok()?;
+        Some(Self {
+            identity: Identity {
+                dev: metadata.dev(),
+                ino: metadata.ino(),
+            },
+            size: metadata.size(),
+            mtime: (metadata.mtime(), metadata.mtime_nsec()),
+        })
+    }
+
+    // Check that name is still the file of the snapshot. Only the identity
+    // of directories is compared, as their size and modification time
+    // change with the renames made inside them.
+    fn verify(&self, num: usize, name: &str) -> Result<(), OpsError> {
+        let current = match Snapshot::of(name) {
+            Some(current) => current,
+            None => return Err(OpsError::NotFound(name.to_string())),
+        };
+
+        if current.identity != self.identity {
+            Err(OpsError::StaleId {
+                num,
+                name: name.to_string(),
+            })
+        } else if !path::is_dir(name) && (current.size, current.mtime) != (self.size, self.mtime) {
+            Err(OpsError::Drift(name.to_string()))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn modified(&self) -> SystemTime {
+        let (sec, nsec) = self.mtime;
+        let nsec = Duration::from_nanos(nsec as u64);
+        match u64::try_from(sec) {
+            Ok(sec) => UNIX_EPOCH + Duration::from_secs(sec) + nsec,
+            Err(_) => UNIX_EPOCH - Duration::from_secs(sec.unsigned_abs()) + nsec,
+        }
+    }
+}
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}.{:09}",
+            self.identity, self.size, self.mtime.0, self.mtime.1
+        )
+    }
+}
+
+impl FromStr for Snapshot {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || anyhow::anyhow!("invalid snapshot {:?}", s);
+        let mut parts = s.splitn(4, ':');
+        let (dev, ino, size, mtime) = match (parts.next(), parts.next(), parts.next(), parts.next())
+        {
+            (Some(dev), Some(ino), Some(size), Some(mtime)) => (dev, ino, size, mtime),
+            _ => return Err(invalid()),
+        };
+        let (sec, nsec) = mtime.split_once('.').ok_or_else(invalid)?;
+
+        Ok(Self {
+            identity: Identity {
+                dev: dev.parse().map_err(|_| invalid())?,
+                ino: ino.parse().map_err(|_| invalid())?,
+            },
+            size: size.parse().map_err(|_| invalid())?,
+            mtime: (
+                sec.parse().map_err(|_| invalid())?,
+                nsec.parse().map_err(|_| invalid())?,
+            ),
+        })
+    }
+}
+
+/// Take a snapshot of every item that exists.
+pub fn snapshot(items: &ItemTable) -> HashMap<usize, Snapshot> {
+    items
+        .iter()
+        .filter_map(|(num, name)| Some((num, Snapshot::of(name)?)))
+        .collect()
 }
 
 pub trait Operation {
@@ -49,43 +243,525 @@ pub trait Operation {
         }
         Ok(())
     }
+
+    // Create an empty file, or a directory if name ends with a slash.
+    fn create(&self, name: &str) -> Result<()> {
+        let res = if name.ends_with('/') {
+            fs::create_dir(name)
+        } else {
+            fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(name)
+                .map(|_| ())
+        };
+
+        if let Err(source) = res {
+            bail!(OpsError::FailCreate {
+                source,
+                name: name.to_string(),
+            })
+        }
+        Ok(())
+    }
+
+    // Replace link atomically by creating the new link under a temporary
+    // name and renaming it over the old one.
+    fn relink(&self, link: &str, target: &str) -> Result<()> {
+        let tmp_name = get_unique_tmp_name(link);
+
+        if let Err(source) = symlink(target, &tmp_name).and_then(|_| fs::rename(&tmp_name, link)) {
+            let _ = fs::remove_file(&tmp_name);
+            bail!(OpsError::FailRelink {
+                source,
+                link: link.to_string(),
+                target: target.to_string()
+            })
+        }
+        Ok(())
+    }
+
+    fn chmod(&self, name: &str, mode: u32) -> Result<()> {
+        if let Err(source) = fs::set_permissions(name, fs::Permissions::from_mode(mode)) {
+            bail!(OpsError::FailChmod {
+                source,
+                name: name.to_string(),
+                mode
+            })
+        }
+        Ok(())
+    }
+
+    // Does not follow symlinks, the link itself is changed.
+    fn chown(&self, name: &str, uid: Option<u32>, gid: Option<u32>) -> Result<()> {
+        if let Err(source) = lchown(name, uid, gid) {
+            bail!(OpsError::FailChown {
+                source,
+                name: name.to_string(),
+            })
+        }
+        Ok(())
+    }
+
+    // Set the modification time and leave the access time untouched.
+    // Does not follow symlinks.
+    fn touch(&self, name: &str, mtime: SystemTime) -> Result<()> {
+        let res = match mtime.duration_since(UNIX_EPOCH) {
+            Ok(since) => utimensat(
+                None,
+                name,
+                &TimeSpec::UTIME_OMIT,
+                &TimeSpec::from_duration(since),
+                UtimensatFlags::NoFollowSymlink,
+            )
+            .map_err(std::io::Error::from),
+            Err(_) => Err(std::io::ErrorKind::InvalidInput.into()),
+        };
+
+        if let Err(source) = res {
+            bail!(OpsError::FailTouch {
+                source,
+                name: name.to_string(),
+            })
+        }
+        Ok(())
+    }
 }
 
 pub struct FS;
 
 impl Operation for FS {}
 
+/// What applying one line of the file list did.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Outcome {
+    /// Number of the listed item, None for created entries and unlisted
+    /// files moved out of the way.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num: Option<usize>,
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "action", rename_all = "kebab-case")]
+pub enum Action {
+    Renamed {
+        from: String,
+        to: String,
+    },
+    Copied {
+        from: String,
+        to: String,
+    },
+    Created {
+        name: String,
+    },
+    /// Only the link target, mode, owner or modification time changed.
+    Updated {
+        name: String,
+    },
+    Unchanged {
+        name: String,
+    },
+    /// Left alone because it has no new name or changed since it was
+    /// listed.
+    Skipped {
+        name: String,
+        reason: String,
+    },
+    Failed {
+        name: String,
+        reason: String,
+    },
+}
+
 pub struct Operator {
-    items: HashMap<usize, String>,
-    dones: HashMap<usize, String>,
+    items: ItemTable,
+    dones: ItemTable,
+    snapshots: HashMap<usize, Snapshot>,
+    // Items checked against their snapshot before they were changed.
+    verified: HashSet<usize>,
+    outcomes: Vec<Outcome>,
+    verbose: bool,
+    verbose_to_stderr: bool,
+    base: Option<PathBuf>,
+    jobs: usize,
+    // Set by apply_all while applying a copy that it runs later, which
+    // then only records the copy in deferred.
+    defer_copy: bool,
+    deferred: Option<(String, String)>,
+    // Changes printed by log, for the tests to check.
+    #[cfg(test)]
+    logged: std::cell::RefCell<Vec<String>>,
+}
+
+// A copy started by apply_all, with the line, item and outcome it belongs to.
+struct PendingCopy {
+    line: usize,
+    num: usize,
+    outcome: usize,
+    from: String,
+    to: String,
+}
+
+// Copies started by apply_all and not done yet, by line, with the paths
+// they read and write indexed to find the copies a line has to wait for.
+#[derive(Default)]
+struct RunningCopies {
+    copies: HashMap<usize, PendingCopy>,
+    reading: ItemTable,
+    writing: ItemTable,
+}
+
+impl RunningCopies {
+    fn is_empty(&self) -> bool {
+        self.copies.is_empty()
+    }
+
+    // Whether a copy of from to to touches a path a running copy writes, or
+    // writes a path a running copy reads.
+    fn conflicts(&self, from: &str, to: &str) -> bool {
+        self.writing.overlaps(to) || self.writing.overlaps(from) || self.reading.overlaps(to)
+    }
+
+    fn insert(&mut self, copy: PendingCopy) {
+        self.reading.insert(copy.line, copy.from.clone());
+        self.writing.insert(copy.line, copy.to.clone());
+        self.copies.insert(copy.line, copy);
+    }
+
+    fn remove(&mut self, line: usize) -> PendingCopy {
+        self.reading.remove(&line);
+        self.writing.remove(&line);
+        self.copies.remove(&line).expect("copy is not running")
+    }
 }
 
 impl Operator {
     // new takes items as arguments
-    pub fn new(items: HashMap<usize, String>) -> Self {
+    pub fn new(items: ItemTable) -> Self {
         let l = items.len();
         Self {
             items,
-            dones: HashMap::with_capacity(l),
+            dones: ItemTable::with_capacity(l),
+            snapshots: HashMap::new(),
+            verified: HashSet::new(),
+            outcomes: Vec::new(),
+            verbose: false,
+            verbose_to_stderr: false,
+            base: None,
+            jobs: 1,
+            defer_copy: false,
+            deferred: None,
+            #[cfg(test)]
+            logged: Default::default(),
+        }
+    }
+
+    /// Print every change made to the file system.
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    /// Print the changes of verbose to stderr instead of stdout, which
+    /// may hold a report.
+    pub fn verbose_to_stderr(mut self, verbose_to_stderr: bool) -> Self {
+        self.verbose_to_stderr = verbose_to_stderr;
+        self
+    }
+
+    /// Check that items are still the files they were listed as, unchanged,
+    /// before touching them.
+    pub fn snapshots(mut self, snapshots: HashMap<usize, Snapshot>) -> Self {
+        self.snapshots = snapshots;
+        self
+    }
+
+    /// Errors for the items that parsed_lines rename, copy or update and
+    /// that changed since their snapshot was taken, in listing order.
+    pub fn drifted(&self, parsed_lines: &[(usize, ParsedLine)]) -> Vec<OpsError> {
+        let mut seen = HashSet::new();
+        let mut changed = HashSet::new();
+        for (_, parsed_line) in parsed_lines {
+            if let Some(num) = parsed_line.num {
+                if !seen.insert(num) || self.changes(num, parsed_line) {
+                    changed.insert(num);
+                }
+            }
         }
+
+        self.items
+            .iter()
+            .filter(|(num, _)| changed.contains(num))
+            .filter_map(|(num, name)| self.snapshots.get(&num)?.verify(num, name).err())
+            .collect()
+    }
+
+    /// Resolve edited names against base, for lists written with
+    /// `Format::base`. Names may leave base with `../`.
+    pub fn base<P: Into<PathBuf>>(mut self, base: P) -> Self {
+        self.base = Some(base.into());
+        self
+    }
+
+    /// Number of copies apply_all runs at once.
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs.max(1);
+        self
+    }
+
+    /// Outcomes of the lines applied so far, in order.
+    pub fn outcomes(&self) -> &[Outcome] {
+        &self.outcomes
     }
 
     pub fn apply_changes<T: Operation>(&mut self, parsed_line: ParsedLine, ops: T) -> Result<()> {
-        let num = &parsed_line.num;
-        let new_name = parsed_line.filename;
-        let done = self.dones.get(num);
-        let item = self.items.get(num);
+        self.apply_recorded(parsed_line, &ops)
+    }
+
+    /// Apply every line and return the result of each, by line.
+    ///
+    /// With more than one job, lines that only copy a file are handed to
+    /// jobs threads as they come. Everything else that changes the file
+    /// system, renames included, runs in order once the running copies are
+    /// done, and so does a copy reading or writing a path written by a
+    /// running copy. Chains, swaps and directory renames thus see the file
+    /// system as if the lines were applied one by one.
+    pub fn apply_all<T: Operation + Sync>(
+        &mut self,
+        parsed_lines: Vec<(usize, ParsedLine)>,
+        ops: &T,
+    ) -> Vec<(usize, Result<()>)> {
+        if self.jobs == 1 {
+            return parsed_lines
+                .into_iter()
+                .map(|(line, parsed_line)| (line, self.apply_recorded(parsed_line, ops)))
+                .collect();
+        }
+
+        let mut results = Vec::with_capacity(parsed_lines.len());
+        let (job_tx, job_rx) = mpsc::sync_channel::<(usize, String, String)>(self.jobs);
+        let (done_tx, done_rx) = mpsc::channel();
+        let job_rx = Mutex::new(job_rx);
+
+        thread::scope(|scope| {
+            for _ in 0..self.jobs {
+                let (job_rx, done_tx) = (&job_rx, done_tx.clone());
+                scope.spawn(move || loop {
+                    // The lock is released once a job is received, not
+                    // held while copying.
+                    let job = job_rx.lock().unwrap().recv();
+                    let (line, from, to) = match job {
+                        Ok(job) => job,
+                        Err(_) => return,
+                    };
+                    if done_tx.send((line, ops.copy(&from, &to))).is_err() {
+                        return;
+                    }
+                });
+            }
+
+            let mut running = RunningCopies::default();
+            for (line, parsed_line) in parsed_lines {
+                // Copies done meanwhile free the paths they used.
+                while let Ok((line, res)) = done_rx.try_recv() {
+                    self.finish_copy(running.remove(line), res, &mut results);
+                }
+
+                let copy = self.plain_copy(&parsed_line);
+                let independent = match &copy {
+                    Some((from, to)) => !running.conflicts(from, to),
+                    // Lines keeping a file as it is, such as those listing
+                    // the originals of copies, do not wait. A file replaced
+                    // by a copy was renamed out of its way, so it is not kept.
+                    None => self.unchanged(&parsed_line),
+                };
+                if !independent {
+                    while !running.is_empty() {
+                        let (line, res) = done_rx.recv().expect("copy threads stopped");
+                        self.finish_copy(running.remove(line), res, &mut results);
+                    }
+                }
+
+                let num = parsed_line.num;
+                self.defer_copy = copy.is_some();
+                let res = self.apply_recorded(parsed_line, ops);
+                self.defer_copy = false;
+
+                match (self.deferred.take(), num) {
+                    (Some((from, to)), Some(num)) => {
+                        running.insert(PendingCopy {
+                            line,
+                            num,
+                            outcome: self.outcomes.len() - 1,
+                            from: from.clone(),
+                            to: to.clone(),
+                        });
+                        job_tx.send((line, from, to)).expect("copy threads stopped");
+                    }
+                    _ => results.push((line, res)),
+                }
+            }
+
+            // Stop the threads once they are done with the queued copies.
+            drop(job_tx);
+            while !running.is_empty() {
+                let (line, res) = done_rx.recv().expect("copy threads stopped");
+                self.finish_copy(running.remove(line), res, &mut results);
+            }
+        });
+
+        results.sort_by_key(|(line, _)| *line);
+        results
+    }
+
+    // Record the result of a copy run by apply_all.
+    fn finish_copy(
+        &mut self,
+        copy: PendingCopy,
+        res: Result<()>,
+        results: &mut Vec<(usize, Result<()>)>,
+    ) {
+        match &res {
+            Ok(_) => self.log(format_args!("'{}' ~> '{}'", copy.from, copy.to)),
+            Err(err) => {
+                // As when the copy fails in apply_line, later copies of the
+                // item read from the file this one read from. They were
+                // waiting for this copy, which writes the file they read.
+                self.dones.insert(copy.num, copy.from.clone());
+                self.outcomes[copy.outcome].action = Action::Failed {
+                    name: copy.from.clone(),
+                    reason: err.to_string(),
+                }
+            }
+        }
+        results.push((copy.line, res));
+    }
+
+    // The source and new name of a line that only copies a regular file,
+    // which apply_all may run alongside other copies.
+    fn plain_copy(&self, parsed_line: &ParsedLine) -> Option<(String, String)> {
+        let num = parsed_line.num?;
+        if parsed_line.target.is_some()
+            || parsed_line.mode.is_some()
+            || parsed_line.uid.is_some()
+            || parsed_line.gid.is_some()
+            || parsed_line.mtime.is_some()
+            || self.items.contains(&num)
+        {
+            return None;
+        }
+
+        let from = self.dones.get(&num)?;
+        let to = self.resolve(&parsed_line.filename);
+        if to.is_empty() || to.ends_with('/') || !Path::new(from).is_file() {
+            return None;
+        }
+        // A file in the way is renamed to a temporary name, which a running
+        // copy may be about to write, so such copies run in order.
+        if !matches!(exists(&to), Ok(false)) {
+            return None;
+        }
+        Some((from.clone(), to))
+    }
+
+    // Whether a line keeps a listed file as it is.
+    fn unchanged(&self, parsed_line: &ParsedLine) -> bool {
+        let item = match parsed_line.num.and_then(|num| self.items.get(&num)) {
+            Some(item) => item,
+            None => return false,
+        };
+        let name = self.resolve(&parsed_line.filename);
+
+        parsed_line.target.is_none()
+            && parsed_line.mode.is_none()
+            && parsed_line.uid.is_none()
+            && parsed_line.gid.is_none()
+            && parsed_line.mtime.is_none()
+            && !name.is_empty()
+            && !name.ends_with('/')
+            && path::same(item, &name)
+    }
+
+    fn apply_recorded<T: Operation>(&mut self, parsed_line: ParsedLine, ops: &T) -> Result<()> {
+        let num = parsed_line.num;
+        let name = num
+            .and_then(|num| self.items.get(&num).or_else(|| self.dones.get(&num)))
+            .cloned()
+            .unwrap_or_else(|| self.resolve(&parsed_line.filename));
+
+        match self.apply_line(parsed_line, ops) {
+            Ok(action) => {
+                self.outcomes.push(Outcome { num, action });
+                Ok(())
+            }
+            Err(err) => {
+                let reason = err.to_string();
+                let action = match err.downcast_ref::<OpsError>() {
+                    Some(OpsError::NotFound(_) | OpsError::StaleId { .. } | OpsError::Drift(_)) => {
+                        Action::Skipped { name, reason }
+                    }
+                    _ => Action::Failed { name, reason },
+                };
+                self.outcomes.push(Outcome { num, action });
+                Err(err)
+            }
+        }
+    }
+
+    fn apply_line<T: Operation>(&mut self, parsed_line: ParsedLine, ops: &T) -> Result<Action> {
+        let new_name = self.resolve(&parsed_line.filename);
+        let num = match parsed_line.num {
+            Some(num) => num,
+            None => {
+                self.create(&new_name, ops)?;
+                return Ok(Action::Created { name: new_name });
+            }
+        };
+
+        // Only items that the line changes must still be the files that
+        // were listed, files changed by others but left alone are fine.
+        if let Some(snapshot) = self.snapshots.get(&num) {
+            let src = self.items.get(&num).or_else(|| self.dones.get(&num));
+            if let Some(src) = src {
+                if !self.verified.contains(&num) && self.changes(num, &parsed_line) {
+                    snapshot.verify(num, src)?;
+                    self.verified.insert(num);
+                }
+            }
+        }
+
+        // Directories are listed with a trailing slash, which must not be
+        // added to anything else.
+        let new_name = match new_name.strip_suffix('/').filter(|name| !name.is_empty()) {
+            Some(name) => {
+                let src = self.items.get(&num).or_else(|| self.dones.get(&num));
+                if let Some(src) = src.filter(|src| !path::is_dir(src)) {
+                    bail!(OpsError::NotDir(src.clone()));
+                }
+                name.trim_end_matches('/').to_string()
+            }
+            None => new_name,
+        };
+        let done = self.dones.get(&num);
+        let item = self.items.get(&num);
         let is_copy = done.is_some();
+        let mut action = None;
 
         // Check if number part is in items or dones.
         if item.is_none() && !is_copy {
-            bail!("unknown item number {}", parsed_line.num);
-        } else if is_copy || *item.unwrap() != new_name {
+            bail!("unknown item number {}", num);
+        } else if is_copy || !path::same(item.unwrap(), &new_name) {
             // Handle move or copy if filename is different or is_copy is true.
 
             // If target filename is empty, skip.
             if new_name.is_empty() {
-                return Ok(());
+                return Ok(Action::Skipped {
+                    name: done.or(item).unwrap().clone(),
+                    reason: "no new name".to_string(),
+                });
             }
 
             let src = match done {
@@ -95,25 +771,49 @@ impl Operator {
             .clone();
 
             // Check if src exists.
-            match Path::new(&src).try_exists() {
+            match exists(&src) {
                 Ok(false) => {
-                    self.items.remove(num);
+                    self.items.remove(&num);
                     bail!(OpsError::NotFound(src))
                 }
                 Err(e) => bail!(e),
                 _ => {}
             }
 
+            // Moving or copying a directory below itself would create the
+            // parent of new_name inside src before failing.
+            if is_inside(&src, &new_name)? {
+                bail!(OpsError::IntoItself {
+                    from: src,
+                    to: new_name,
+                })
+            }
+
             let new_name_path = Path::new(&new_name);
+            let mut from = src.clone();
 
             // Deal with swaps.
-            if let Ok(true) = new_name_path.try_exists() {
+            if !is_copy && is_alias(&src, &new_name) {
+                // new_name is src itself, e.g. with a different case on a
+                // case-insensitive file system, where renaming directly may
+                // do nothing. Go through a temporary name instead.
+                from = get_unique_tmp_name(&src);
+                ops.rename(&src, &from)?;
+                self.log(format_args!("'{}' -> '{}'", src, from));
+            } else if let Ok(true) = exists(&new_name) {
                 let tmp_name = get_unique_tmp_name(&new_name);
                 ops.rename(&new_name, &tmp_name)?;
-
-                // TODO: log
-                // print "'$name' -> '$tmp'\n";
-
+                self.log(format_args!("'{}' -> '{}'", new_name, tmp_name));
+
+                // The file moved out of the way is part of the outcomes,
+                // whether it is listed or not.
+                self.outcomes.push(Outcome {
+                    num: self.items.find(&new_name),
+                    action: Action::Renamed {
+                        from: new_name.clone(),
+                        to: tmp_name.clone(),
+                    },
+                });
                 self.update_items(&new_name, &tmp_name);
             }
 
@@ -124,371 +824,1980 @@ impl Operator {
                 }
             }
 
-            if is_copy {
+            let deferred = is_copy && self.defer_copy;
+            if deferred {
+                self.deferred = Some((src.clone(), new_name.clone()));
+            } else if is_copy {
                 ops.copy(&src, &new_name)?;
             } else {
-                ops.rename(&src, &new_name)?;
+                ops.rename(&from, &new_name)?;
             }
 
             // If name is directory, update all items that start with name.
             if new_name_path.is_dir() {
                 self.update_dir(&src, &new_name);
             }
-            // TODO: log
-            // if ($opt_verbose) {
-            //   print "'$src' => '$name'\n" unless $iscopy;
-            //   print "'$src' ~> '$name'\n" if $iscopy;
-            // }
+
+            if !deferred {
+                if is_copy {
+                    self.log(format_args!("'{}' ~> '{}'", src, new_name));
+                } else {
+                    self.log(format_args!("'{}' => '{}'", src, new_name));
+                }
+            }
+
+            let (from, to) = (src, new_name.clone());
+            action = Some(if is_copy {
+                Action::Copied { from, to }
+            } else {
+                Action::Renamed { from, to }
+            });
+        }
+
+        // Updates are printed like in the preview.
+        let mut updated = false;
+        if let Some(target) = parsed_line.target {
+            if retarget(&new_name, &target, ops)? {
+                self.log(format_args!("relink '{}' -> '{}'", new_name, target));
+                updated = true;
+            }
         }
+        if let Some(mode) = parsed_line.mode {
+            if set_mode(&new_name, mode, ops)? {
+                let mode = meta::format_mode(mode);
+                self.log(format_args!("chmod '{}' {}", new_name, mode));
+                updated = true;
+            }
+        }
+        if parsed_line.uid.is_some() || parsed_line.gid.is_some() {
+            if let Some((uid, gid)) = set_owner(&new_name, parsed_line.uid, parsed_line.gid, ops)? {
+                let owner = meta::format_owner(uid, gid);
+                self.log(format_args!("chown '{}' {}", new_name, owner));
+                updated = true;
+            }
+        }
+        if let Some(mtime) = parsed_line
+            .mtime
+            .filter(|&mtime| self.mtime_edited(num, mtime))
+        {
+            if set_mtime(&new_name, mtime, ops)? {
+                let mtime = meta::format_mtime(mtime);
+                self.log(format_args!("touch '{}' {}", new_name, mtime));
+                updated = true;
+            }
+        }
+
+        let action = action.unwrap_or_else(|| {
+            let name = new_name.clone();
+            if updated {
+                Action::Updated { name }
+            } else {
+                Action::Unchanged { name }
+            }
+        });
 
-        self.dones.insert(*num, new_name);
-        self.items.remove(num);
+        self.dones.insert(num, new_name);
+        self.items.remove(&num);
 
-        Ok(())
+        Ok(action)
     }
 
-    fn update_items(&mut self, from: &str, to: &str) {
-        for (_, name) in self.items.iter_mut() {
-            if name == from {
-                *name = to.to_string();
+    /// Describe the changes parsed_lines would make, without touching the
+    /// file system. Renames and copies use the notation of verbose output.
+    ///
+    /// The lines are planned the way they are applied, on a copy of the
+    /// items, so that files moved out of the way by swaps are listed and
+    /// later lines see the names that earlier ones changed.
+    pub fn preview(&self, parsed_lines: &[(usize, ParsedLine)]) -> Vec<String> {
+        let mut items = self.items.clone();
+        let mut dones: HashMap<usize, String> = HashMap::new();
+        // Whether the names changed by the lines planned so far exist, the
+        // others are looked up on the file system.
+        let cwd = env::current_dir().ok();
+        let mut planned: HashMap<PathBuf, bool> = HashMap::new();
+        let resolve = |name: &str| path::resolve_from(name, cwd.as_deref());
+        let exists =
+            |planned: &HashMap<PathBuf, bool>, name: &str| match planned.get(&resolve(name)) {
+                Some(exists) => *exists,
+                None => exists(name).unwrap_or(false),
+            };
+        let mut changes = Vec::new();
+
+        for (_, parsed_line) in parsed_lines {
+            let name = self.resolve(&parsed_line.filename);
+            let num = match parsed_line.num {
+                Some(num) => num,
+                None => {
+                    changes.push(format!("create '{}'", name));
+                    planned.insert(resolve(&name), true);
+                    continue;
+                }
+            };
+            // The trailing slash of directories is checked when the changes
+            // are applied.
+            let name = name.trim_end_matches('/').to_string();
+
+            // Unknown numbers are reported when the changes are applied.
+            let listed = match self.items.get(&num).or_else(|| self.dones.get(&num)) {
+                Some(listed) => listed,
+                None => continue,
+            };
+            if name.is_empty() {
+                continue;
+            }
+
+            // Items only matter until their first line, later lines copy
+            // them, so the items of done lines are left in place.
+            let copy = dones.get(&num).or_else(|| self.dones.get(&num)).cloned();
+            let src = match (&copy, items.get(&num)) {
+                (Some(src), _) => src.clone(),
+                (None, Some(item)) if !path::same(item.as_str(), &name) => item.clone(),
+                _ => {
+                    dones.insert(num, name.clone());
+                    changes.extend(self.attribute_changes(num, listed, &name, parsed_line));
+                    continue;
+                }
+            };
+
+            // Names that exist are moved out of the way, or are another
+            // spelling of src that is renamed through a temporary name.
+            if exists(&planned, &name) {
+                if copy.is_none() && is_alias(&src, &name) {
+                    let tmp_name = unique_tmp_name(&src, |name| exists(&planned, name));
+                    changes.push(format!("'{}' -> '{}'", src, tmp_name));
+                } else {
+                    let tmp_name = unique_tmp_name(&name, |name| exists(&planned, name));
+                    changes.push(format!("'{}' -> '{}'", name, tmp_name));
+                    planned.insert(resolve(&tmp_name), true);
+                    items.rename(&name, &tmp_name);
+                }
+            }
+
+            if copy.is_some() {
+                changes.push(format!("'{}' ~> '{}'", src, name));
+            } else {
+                changes.push(format!("'{}' => '{}'", src, name));
+                planned.insert(resolve(&src), false);
+                if path::is_dir(listed) {
+                    items.rename_dir(&src, &name);
+                }
             }
+            planned.insert(resolve(&name), true);
+            dones.entry(num).or_insert_with(|| name.clone());
+
+            // Attributes are compared with those of the listed file.
+            changes.extend(self.attribute_changes(num, listed, &name, parsed_line));
         }
+        changes
     }
 
-    fn update_dir(&mut self, from: &str, to: &str) {
-        for (_, name) in self.items.iter_mut() {
-            if name.starts_with(from) {
-                *name = to.to_string() + &name[from.len()..];
+    // Whether applying parsed_line renames, copies or updates item num.
+    fn changes(&self, num: usize, parsed_line: &ParsedLine) -> bool {
+        if self.dones.contains(&num) {
+            return true;
+        }
+        let item = match self.items.get(&num) {
+            Some(item) => item,
+            None => return false,
+        };
+        let name = self.resolve(&parsed_line.filename);
+        let name = name.trim_end_matches('/');
+
+        !name.is_empty()
+            && (!path::same(item.as_str(), name)
+                || !self
+                    .attribute_changes(num, item, name, parsed_line)
+                    .is_empty())
+    }
+
+    // Describe the attributes of the file item that parsed_line changes,
+    // naming it name.
+    fn attribute_changes(
+        &self,
+        num: usize,
+        item: &str,
+        name: &str,
+        parsed_line: &ParsedLine,
+    ) -> Vec<String> {
+        let mut changes = Vec::new();
+        if parsed_line.target.is_none()
+            && parsed_line.mode.is_none()
+            && parsed_line.uid.is_none()
+            && parsed_line.gid.is_none()
+            && parsed_line.mtime.is_none()
+        {
+            return changes;
+        }
+
+        let metadata = match fs::symlink_metadata(item) {
+            Ok(metadata) => metadata,
+            Err(_) => return changes,
+        };
+
+        if let Some(target) = &parsed_line.target {
+            if fs::read_link(item).map_or(true, |current| current != Path::new(target)) {
+                changes.push(format!("relink '{}' -> '{}'", name, target));
+            }
+        }
+        if let Some(mode) = parsed_line.mode {
+            if metadata.permissions().mode() & 0o7777 != mode {
+                changes.push(format!("chmod '{}' {}", name, meta::format_mode(mode)));
             }
         }
+        let uid = parsed_line.uid.unwrap_or(metadata.uid());
+        let gid = parsed_line.gid.unwrap_or(metadata.gid());
+        if uid != metadata.uid() || gid != metadata.gid() {
+            changes.push(format!("chown '{}' {}", name, meta::format_owner(uid, gid)));
+        }
+        if let Some(mtime) = parsed_line.mtime {
+            if self.mtime_edited(num, mtime) && !same_mtime(&metadata, mtime) {
+                changes.push(format!("touch '{}' {}", name, meta::format_mtime(mtime)));
+            }
+        }
+        changes
     }
-}
 
-fn get_unique_tmp_name(name: &str) -> String {
-    let mut new_name = name.to_string();
-    new_name.push('~');
+    fn create<T: Operation>(&mut self, name: &str, ops: &T) -> Result<()> {
+        if exists(name)? {
+            bail!(OpsError::AlreadyExists(name.to_string()));
+        }
 
-    let mut i = 1;
-    while Path::new(&new_name).try_exists().unwrap() {
-        new_name.push_str(&i.to_string());
-        i += 1;
+        if let Some(parent) = Path::new(name.trim_end_matches('/')).parent() {
+            if !parent.exists() {
+                fs::create_dir_all(parent)?;
+            }
+        }
+
+        ops.create(name)?;
+        self.log(format_args!("created '{}'", name));
+        Ok(())
     }
-    new_name
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use assert_fs::prelude::*;
-    use predicates::prelude::*;
+    // Print a change made to the file system when verbose.
+    fn log(&self, change: fmt::Arguments) {
+        #[cfg(test)]
+        if self.verbose {
+            self.logged.borrow_mut().push(change.to_string());
+        }
+        match (self.verbose, self.verbose_to_stderr) {
+            (false, _) => {}
+            (true, false) => println!("{}", change),
+            (true, true) => eprintln!("{}", change),
+        }
+    }
 
-    #[test]
-    fn test_apply_changes_unknown_number() {
-        let items = HashMap::from([(2, "file_2".to_string())]);
+    // Whether the modification time of item num reads differently from the
+    // one it was listed with. Comparing times instead would move files
+    // listed in the repeated hour of a DST change, and reset directories
+    // whose children were renamed. Items without a snapshot are compared
+    // with the file itself.
+    fn mtime_edited(&self, num: usize, mtime: SystemTime) -> bool {
+        match self.snapshots.get(&num) {
+            Some(snapshot) => meta::format_mtime(snapshot.modified()) != meta::format_mtime(mtime),
+            None => true,
+        }
+    }
 
-        let mut operator = Operator::new(items.to_owned());
+    fn resolve(&self, name: &str) -> String {
+        match &self.base {
+            Some(base) if !name.is_empty() => path::join(base, name),
+            _ => name.to_string(),
+        }
+    }
+
+    fn update_items(&mut self, from: &str, to: &str) {
+        self.items.rename(from, to);
+    }
+
+    // Move items below the directory from to the directory to, comparing
+    // whole path components.
+    fn update_dir(&mut self, from: &str, to: &str) {
+        self.items.rename_dir(from, to);
+    }
+}
+
+/// Check that no two lines name the same file. Applying them would move the
+/// first file out of the way of the second one, so every conflict is
+/// reported with its line numbers before anything is touched. Names are
+/// resolved against base like `Operator::base` does.
+pub fn check_duplicates(parsed_lines: &[(usize, ParsedLine)], base: Option<&Path>) -> Result<()> {
+    let cwd = env::current_dir().ok();
+    let mut lines: HashMap<PathBuf, (&str, Vec<usize>)> = HashMap::new();
+    for (line, parsed_line) in parsed_lines {
+        let name = parsed_line.filename.trim_end_matches('/');
+        if !name.is_empty() {
+            let resolved = match base {
+                Some(base) => path::resolve_from(path::join(base, name), cwd.as_deref()),
+                None => path::resolve_from(name, cwd.as_deref()),
+            };
+            let entry = lines.entry(resolved).or_insert((name, Vec::new()));
+            entry.1.push(*line);
+        }
+    }
+
+    let mut duplicates: Vec<_> = lines.into_values().filter(|(_, l)| l.len() > 1).collect();
+    if duplicates.is_empty() {
+        return Ok(());
+    }
+    duplicates.sort_by_key(|(_, l)| l[0]);
+
+    let errors: Vec<_> = duplicates
+        .iter()
+        .map(|(name, l)| {
+            let l: Vec<_> = l.iter().map(|n| n.to_string()).collect();
+            format!("lines {}: '{}' is named more than once", l.join(", "), name)
+        })
+        .collect();
+    bail!(errors.join("\n"))
+}
+
+// Point the symlink link to target unless it already does. The helpers
+// below return whether they changed anything.
+fn retarget<T: Operation>(link: &str, target: &str, ops: &T) -> Result<bool> {
+    match fs::read_link(link) {
+        Ok(current) if current == Path::new(target) => Ok(false),
+        Ok(_) => ops.relink(link, target).map(|_| true),
+        Err(_) => bail!(OpsError::NotSymlink(link.to_string())),
+    }
+}
+
+// Change the permission bits of name if they differ from mode.
+fn set_mode<T: Operation>(name: &str, mode: u32, ops: &T) -> Result<bool> {
+    let metadata = fs::symlink_metadata(name)?;
+    if metadata.permissions().mode() & 0o7777 == mode {
+        return Ok(false);
+    }
+
+    // The mode of a symlink cannot be changed and chmod would change
+    // the file it points to instead.
+    if metadata.file_type().is_symlink() {
+        bail!(OpsError::FailChmod {
+            source: std::io::ErrorKind::Unsupported.into(),
+            name: name.to_string(),
+            mode
+        })
+    }
+    ops.chmod(name, mode).map(|_| true)
+}
+
+// Change the owner and group of name if they differ from uid and gid, and
+// return the new owner and group if they did.
+fn set_owner<T: Operation>(
+    name: &str,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    ops: &T,
+) -> Result<Option<(u32, u32)>> {
+    let metadata = fs::symlink_metadata(name)?;
+    let uid = uid.filter(|&uid| uid != metadata.uid());
+    let gid = gid.filter(|&gid| gid != metadata.gid());
+
+    if uid.is_none() && gid.is_none() {
+        return Ok(None);
+    }
+    ops.chown(name, uid, gid)?;
+    Ok(Some((
+        uid.unwrap_or(metadata.uid()),
+        gid.unwrap_or(metadata.gid()),
+    )))
+}
+
+// Change the modification time of name if it differs from mtime. The
+// buffer only shows whole seconds so the current time is compared at
+// that precision, otherwise every untouched line would be a change.
+fn set_mtime<T: Operation>(name: &str, mtime: SystemTime, ops: &T) -> Result<bool> {
+    if same_mtime(&fs::symlink_metadata(name)?, mtime) {
+        return Ok(false);
+    }
+    ops.touch(name, mtime).map(|_| true)
+}
+
+// Whether name is below the directory dir once both are normalized.
+fn is_inside(dir: &str, name: &str) -> std::io::Result<bool> {
+    if !fs::symlink_metadata(dir)?.is_dir() {
+        return Ok(false);
+    }
+    let dir = path::absolute(dir)?;
+    let name = path::absolute(name)?;
+
+    Ok(name != dir && name.starts_with(&dir))
+}
+
+fn same_mtime(metadata: &Metadata, mtime: SystemTime) -> bool {
+    let current = UNIX_EPOCH + Duration::from_secs(metadata.mtime() as u64);
+    metadata.mtime() >= 0 && current == mtime
+}
+
+// Whether to is another spelling of the directory entry from, such as a
+// different case on a case-insensitive file system or a different Unicode
+// normalization. Hard links share the inode but are separate entries, so
+// they are not aliases.
+fn is_alias(from: &str, to: &str) -> bool {
+    if from == to || !same_file(from, to) {
+        return false;
+    }
+
+    let (from_parent, from_name) = split_entry(from);
+    let (to_parent, to_name) = split_entry(to);
+
+    let same_entry = from_name == to_name && same_file(from_parent, to_parent);
+    same_entry || !has_entry(to_parent, to_name)
+}
+
+fn same_file<P: AsRef<Path>>(a: P, b: P) -> bool {
+    match (fs::symlink_metadata(a), fs::symlink_metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+// Split name into its parent directory and last component.
+fn split_entry(name: &str) -> (&Path, &OsStr) {
+    let path = Path::new(name);
+    let parent = match path.parent() {
+        Some(parent) if parent != Path::new("") => parent,
+        _ => Path::new("."),
+    };
+    (parent, path.file_name().unwrap_or_default())
+}
+
+// Whether dir has an entry spelled exactly as name.
+fn has_entry(dir: &Path, name: &OsStr) -> bool {
+    match fs::read_dir(dir) {
+        Ok(entries) => entries.flatten().any(|entry| entry.file_name() == name),
+        Err(_) => false,
+    }
+}
+
+// Like Path::try_exists but does not follow symlinks, so that dangling
+// symlinks are treated as existing files.
+fn exists(name: &str) -> std::io::Result<bool> {
+    match fs::symlink_metadata(name) {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+fn get_unique_tmp_name(name: &str) -> String {
+    unique_tmp_name(name, |name| exists(name).unwrap())
+}
+
+// The first of name~, name~1, name~12 and so on for which exists is false.
+fn unique_tmp_name<E: Fn(&str) -> bool>(name: &str, exists: E) -> String {
+    let mut new_name = name.to_string();
+    new_name.push('~');
+
+    let mut i = 1;
+    while exists(&new_name) {
+        new_name.push_str(&i.to_string());
+        i += 1;
+    }
+    new_name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::prelude::*;
+    use predicates::prelude::*;
+
+    // Operation that fails on every call, for checking that nothing is
+    // attempted.
+    // Lines numbered from 1, each naming its item.
+    fn numbered(lines: &[(usize, String)]) -> Vec<(usize, ParsedLine)> {
+        lines
+            .iter()
+            .enumerate()
+            .map(|(i, (num, filename))| {
+                let parsed_line = ParsedLine {
+                    num: Some(*num),
+                    filename: filename.clone(),
+                    ..Default::default()
+                };
+                (i + 1, parsed_line)
+            })
+            .collect()
+    }
+
+    struct Failing;
+
+    impl Operation for Failing {
+        fn rename(&self, _: &str, _: &str) -> Result<()> {
+            bail!("unexpected rename")
+        }
+
+        fn copy(&self, _: &str, _: &str) -> Result<()> {
+            bail!("unexpected copy")
+        }
+
+        fn create(&self, _: &str) -> Result<()> {
+            bail!("unexpected create")
+        }
+
+        fn relink(&self, _: &str, _: &str) -> Result<()> {
+            bail!("unexpected relink")
+        }
+
+        fn chmod(&self, _: &str, _: u32) -> Result<()> {
+            bail!("unexpected chmod")
+        }
+
+        fn chown(&self, _: &str, _: Option<u32>, _: Option<u32>) -> Result<()> {
+            bail!("unexpected chown")
+        }
+
+        fn touch(&self, _: &str, _: SystemTime) -> Result<()> {
+            bail!("unexpected touch")
+        }
+    }
+
+    #[test]
+    fn test_apply_changes_unknown_number() {
+        let items = ItemTable::from([(2, "file_2".to_string())]);
+
+        let mut operator = Operator::new(items.to_owned());
 
         // Operate on item that does not exist in Operator.items.
         let res = operator.apply_changes(
             ParsedLine {
-                num: 1,
+                num: Some(1),
+                filename: "file_one".to_string(),
+                ..Default::default()
+            },
+            FS,
+        );
+
+        assert_eq!(res.unwrap_err().to_string(), "unknown item number 1");
+        assert_eq!(operator.items, items);
+        assert!(operator.dones.is_empty());
+    }
+
+    #[test]
+    fn test_apply_changes_empty_filename() {
+        let items = ItemTable::from([(1, "file_1".to_string())]);
+
+        let mut operator = Operator::new(items.clone());
+
+        let res = operator.apply_changes(
+            ParsedLine {
+                num: Some(1),
+                filename: "".to_string(),
+                ..Default::default()
+            },
+            FS,
+        );
+
+        assert!(res.is_ok());
+        assert_eq!(operator.items, items);
+        assert!(operator.dones.is_empty());
+    }
+
+    #[test]
+    fn test_apply_changes_src_not_exists() {
+        let items = ItemTable::from([(1, "file_1".to_string())]);
+
+        let mut operator = Operator::new(items);
+
+        // File we want to rename from does not exist.
+        let res = operator.apply_changes(
+            ParsedLine {
+                num: Some(1),
                 filename: "file_one".to_string(),
+                ..Default::default()
+            },
+            FS,
+        );
+
+        assert_eq!(res.unwrap_err().to_string(), "file_1 does not exist");
+        assert!(operator.items.is_empty());
+        assert!(operator.dones.is_empty());
+    }
+
+    #[test]
+    fn test_apply_changes_src_exists() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let temp_str = temp.to_str().unwrap();
+        let file_1 = temp.child("file_1");
+        file_1.touch().unwrap();
+
+        let items = ItemTable::from([(1, file_1.path().to_str().unwrap().to_string())]);
+
+        let mut operator = Operator::new(items);
+
+        let want_dones = ItemTable::from([(1, temp_str.to_owned() + "/file_one")]);
+
+        let res = operator.apply_changes(
+            ParsedLine {
+                num: Some(1),
+                filename: temp_str.to_owned() + "/file_one",
+                ..Default::default()
+            },
+            FS,
+        );
+
+        assert!(res.is_ok());
+        assert!(operator.items.is_empty());
+        assert_eq!(operator.dones, want_dones);
+
+        temp.child("file_1").assert(predicate::path::missing());
+        temp.child("file_one").assert(predicate::path::exists());
+    }
+
+    #[test]
+    fn test_apply_changes_swap() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let temp_str = temp.to_str().unwrap();
+        let file_1 = temp.child("file_1");
+        let file_2 = temp.child("file_2");
+        file_1.touch().unwrap();
+        file_2.touch().unwrap();
+
+        let items = [(1, file_1), (2, file_2)]
+            .into_iter()
+            .map(|(k, v)| (k, v.to_str().unwrap().to_string()))
+            .collect();
+
+        let mut operator = Operator::new(items);
+
+        let want_items = ItemTable::from([(2, temp_str.to_owned() + "/file_2~")]);
+        let want_dones = ItemTable::from([(1, temp_str.to_owned() + "/file_2")]);
+
+        // Rename item 1 to the same name as item 2.
+        // Therefore, item 2 has to be renamed to item 2~.
+        let res = operator.apply_changes(
+            ParsedLine {
+                num: Some(1),
+                filename: temp_str.to_owned() + "/file_2",
+                ..Default::default()
             },
             FS,
         );
 
-        assert_eq!(res.unwrap_err().to_string(), "unknown item number 1");
-        assert_eq!(operator.items, items);
-        assert!(operator.dones.is_empty());
+        assert!(res.is_ok());
+        assert_eq!(operator.items, want_items);
+        assert_eq!(operator.dones, want_dones);
+
+        temp.child("file_1").assert(predicate::path::missing());
+        temp.child("file_2").assert(predicate::path::exists());
+        temp.child("file_2~").assert(predicate::path::exists());
+
+        // The file moved out of the way has an outcome of its own.
+        let file = |name: &str| format!("{}/{}", temp_str, name);
+        assert_eq!(
+            operator.outcomes(),
+            [
+                Outcome {
+                    num: Some(2),
+                    action: Action::Renamed {
+                        from: file("file_2"),
+                        to: file("file_2~"),
+                    },
+                },
+                Outcome {
+                    num: Some(1),
+                    action: Action::Renamed {
+                        from: file("file_1"),
+                        to: file("file_2"),
+                    },
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_apply_changes_copy() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let temp_str = temp.to_str().unwrap();
+        let file_1 = temp.child("file_1");
+        file_1.touch().unwrap();
+
+        let items = [(1, file_1)]
+            .into_iter()
+            .map(|(k, v)| (k, v.to_str().unwrap().to_string()))
+            .collect();
+
+        let mut operator = Operator::new(items);
+
+        // First call changes nothing because the name is the same as in items.
+        {
+            let want_dones = ItemTable::from([(1, temp_str.to_owned() + "/file_1")]);
+
+            let res = operator.apply_changes(
+                ParsedLine {
+                    num: Some(1),
+                    filename: temp_str.to_owned() + "/file_1",
+                    ..Default::default()
+                },
+                FS,
+            );
+
+            assert!(res.is_ok());
+            assert!(operator.items.is_empty());
+            assert_eq!(operator.dones, want_dones);
+        }
+
+        // Second call is a copy because it has the same number.
+        {
+            let want_dones = ItemTable::from([(1, temp_str.to_owned() + "/file_1_copy")]);
+
+            let res = operator.apply_changes(
+                ParsedLine {
+                    num: Some(1),
+                    filename: temp_str.to_owned() + "/file_1_copy",
+                    ..Default::default()
+                },
+                FS,
+            );
+
+            assert!(res.is_ok());
+            assert!(operator.items.is_empty());
+            assert_eq!(operator.dones, want_dones);
+        }
+
+        temp.child("file_1").assert(predicate::path::exists());
+        temp.child("file_1_copy").assert(predicate::path::exists());
+    }
+
+    #[test]
+    fn test_apply_changes_rename_directory() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let temp_str = temp.to_str().unwrap();
+        let temp_sub = temp.child("dir_1");
+        let temp_sub_str = temp_sub.to_str().unwrap();
+        temp_sub.create_dir_all().unwrap();
+        let file_1 = temp_sub.child("file_1");
+        file_1.touch().unwrap();
+
+        let items = ItemTable::from([
+            (1, temp_sub_str.to_owned()),
+            (2, file_1.path().to_str().unwrap().to_string()),
+        ]);
+
+        let mut operator = Operator::new(items);
+
+        let want_items = ItemTable::from([(2, temp_str.to_owned() + "/dir_one/file_1")]);
+        let want_dones = ItemTable::from([(1, temp_str.to_owned() + "/dir_one")]);
+
+        let res = operator.apply_changes(
+            ParsedLine {
+                num: Some(1),
+                filename: temp_str.to_owned() + "/dir_one",
+                ..Default::default()
+            },
+            FS,
+        );
+
+        assert!(res.is_ok());
+        assert_eq!(operator.items, want_items);
+        assert_eq!(operator.dones, want_dones);
+
+        temp.child("dir_1").assert(predicate::path::missing());
+        temp.child("dir_one/file_1")
+            .assert(predicate::path::exists());
+    }
+
+    #[test]
+    fn test_apply_changes_subdirectory() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let temp_str = temp.to_str().unwrap();
+        let file_1 = temp.child("file_1");
+        file_1.touch().unwrap();
+
+        let items = ItemTable::from([(2, file_1.path().to_str().unwrap().to_string())]);
+
+        let mut operator = Operator::new(items);
+
+        let want_dones = ItemTable::from([(2, temp_str.to_owned() + "/subdir/file_one")]);
+
+        let res = operator.apply_changes(
+            ParsedLine {
+                num: Some(2),
+                filename: temp_str.to_owned() + "/subdir/file_one",
+                ..Default::default()
+            },
+            FS,
+        );
+
+        assert!(res.is_ok());
+        assert!(operator.items.is_empty());
+        assert_eq!(operator.dones, want_dones);
+
+        temp.child("subdir/file_one")
+            .assert(predicate::path::exists());
+    }
+
+    #[test]
+    fn test_update_dir() {
+        // This is okay because we will catch and ignore using
+        // NotFound error.
+        let mut operator = Operator::new(
+            [(1, "./src/testdata/file2"), (2, "./src/testdata/file1")]
+                .into_iter()
+                .map(|(k, v)| (k, v.to_string()))
+                .collect(),
+        );
+
+        let want_items = [(1, "./src/test/file2"), (2, "./src/test/file1")]
+            .into_iter()
+            .map(|(k, v)| (k, v.to_string()))
+            .collect();
+
+        operator.update_dir("./src/testdata/", "./src/test/");
+
+        assert_eq!(operator.items, want_items);
+    }
+
+    #[test]
+    fn test_update_dir_odd_case() {
+        // This is okay because we will catch and ignore using
+        // NotFound error.
+        let mut operator = Operator::new(
+            [(1, "./src/testdata/file2"), (2, "./src/testdata/file1")]
+                .into_iter()
+                .map(|(k, v)| (k, v.to_string()))
+                .collect(),
+        );
+
+        let want_items = [(1, "./src/test/file2"), (2, "./src/test/file1")]
+            .into_iter()
+            .map(|(k, v)| (k, v.to_string()))
+            .collect();
+
+        operator.update_dir("./src/testdata/", "./src/test");
+
+        assert_eq!(operator.items, want_items);
+    }
+
+    #[test]
+    fn test_get_unique_tmp_name_first_try() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let temp_str = temp.to_str().unwrap();
+        let file_1 = temp.child("file_1");
+        file_1.touch().unwrap();
+
+        let got = get_unique_tmp_name(&(temp_str.to_owned() + "/file_1"));
+        assert_eq!(temp_str.to_owned() + "/file_1~", got);
+    }
+
+    #[test]
+    fn test_get_unique_tmp_name_1() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let temp_str = temp.to_str().unwrap();
+        let file_1a = temp.child("file_1");
+        let file_1b = temp.child("file_1~");
+        file_1a.touch().unwrap();
+        file_1b.touch().unwrap();
+
+        let got = get_unique_tmp_name(&(temp_str.to_owned() + "/file_1"));
+        assert_eq!(temp_str.to_owned() + "/file_1~1", got);
+    }
+
+    #[test]
+    fn test_apply_changes_relink() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let link = temp.child("current");
+        temp.child("v1").create_dir_all().unwrap();
+        temp.child("v2").create_dir_all().unwrap();
+        link.symlink_to_dir("v1").unwrap();
+
+        let link_str = link.to_str().unwrap().to_string();
+        let items = ItemTable::from([(1, link_str.clone())]);
+
+        let mut operator = Operator::new(items);
+
+        let res = operator.apply_changes(
+            ParsedLine {
+                num: Some(1),
+                filename: link_str.clone(),
+                target: Some("v2".to_string()),
+                ..Default::default()
+            },
+            FS,
+        );
+
+        assert!(res.is_ok());
+        assert_eq!(fs::read_link(link.path()).unwrap(), Path::new("v2"));
+        temp.child("current~").assert(predicate::path::missing());
+    }
+
+    #[test]
+    fn test_apply_changes_relink_not_symlink() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let file_1 = temp.child("file_1");
+        file_1.touch().unwrap();
+
+        let file_1_str = file_1.to_str().unwrap().to_string();
+        let items = ItemTable::from([(1, file_1_str.clone())]);
+
+        let mut operator = Operator::new(items);
+
+        let res = operator.apply_changes(
+            ParsedLine {
+                num: Some(1),
+                filename: file_1_str.clone(),
+                target: Some("file_2".to_string()),
+                ..Default::default()
+            },
+            FS,
+        );
+
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            file_1_str + " is not a symlink"
+        );
+    }
+
+    #[test]
+    fn test_apply_changes_chmod() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let file_1 = temp.child("file_1");
+        file_1.touch().unwrap();
+        fs::set_permissions(file_1.path(), fs::Permissions::from_mode(0o644)).unwrap();
+
+        let file_1_str = file_1.to_str().unwrap().to_string();
+        let items = ItemTable::from([(1, file_1_str.clone())]);
+
+        let mut operator = Operator::new(items);
+
+        let res = operator.apply_changes(
+            ParsedLine {
+                num: Some(1),
+                filename: temp.to_str().unwrap().to_owned() + "/file_one",
+                mode: Some(0o600),
+                ..Default::default()
+            },
+            FS,
+        );
+
+        assert!(res.is_ok());
+        let metadata = fs::metadata(temp.child("file_one").path()).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o7777, 0o600);
+    }
+
+    #[test]
+    fn test_apply_changes_verbose() {
+        // Owners are only recorded, changing them needs root.
+        struct FakeChown;
+
+        impl Operation for FakeChown {
+            fn chown(&self, _: &str, _: Option<u32>, _: Option<u32>) -> Result<()> {
+                Ok(())
+            }
+        }
+
+        let temp = assert_fs::TempDir::new().unwrap();
+        let name = |name: &str| temp.child(name).to_str().unwrap().to_string();
+        temp.child("file_1").touch().unwrap();
+        fs::set_permissions(name("file_1"), fs::Permissions::from_mode(0o644)).unwrap();
+        temp.child("link").symlink_to_file("file_1").unwrap();
+
+        let metadata = fs::metadata(name("file_1")).unwrap();
+        let mtime = UNIX_EPOCH + std::time::Duration::from_secs(1682935200);
+        let items = ItemTable::from([(1, name("file_1")), (2, name("link"))]);
+        let mut operator = Operator::new(items).verbose(true);
+
+        let res = operator.apply_changes(
+            ParsedLine {
+                num: Some(1),
+                filename: name("file_1"),
+                mode: Some(0o600),
+                uid: Some(metadata.uid() + 1),
+                mtime: Some(mtime),
+                ..Default::default()
+            },
+            FakeChown,
+        );
+        assert!(res.is_ok());
+        let res = operator.apply_changes(
+            ParsedLine {
+                num: Some(2),
+                filename: name("link"),
+                target: Some("file_2".to_string()),
+                ..Default::default()
+            },
+            FakeChown,
+        );
+        assert!(res.is_ok());
+
+        let owner = meta::format_owner(metadata.uid() + 1, metadata.gid());
+        assert_eq!(
+            *operator.logged.borrow(),
+            vec![
+                format!("chmod '{}' rw-------", name("file_1")),
+                format!("chown '{}' {}", name("file_1"), owner),
+                format!("touch '{}' {}", name("file_1"), meta::format_mtime(mtime)),
+                format!("relink '{}' -> 'file_2'", name("link")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_apply_changes_chown_unchanged() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let file_1 = temp.child("file_1");
+        file_1.touch().unwrap();
+
+        let metadata = fs::metadata(file_1.path()).unwrap();
+        let file_1_str = file_1.to_str().unwrap().to_string();
+        let items = ItemTable::from([(1, file_1_str.clone())]);
+
+        let mut operator = Operator::new(items);
+
+        // Owner is unchanged so no chown is attempted, which would fail
+        // when not running as root.
+        let res = operator.apply_changes(
+            ParsedLine {
+                num: Some(1),
+                filename: file_1_str,
+                uid: Some(metadata.uid()),
+                gid: Some(metadata.gid()),
+                ..Default::default()
+            },
+            Failing,
+        );
+
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn test_apply_changes_touch() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let file_1 = temp.child("file_1");
+        file_1.touch().unwrap();
+
+        let file_1_str = file_1.to_str().unwrap().to_string();
+        let items = ItemTable::from([(1, file_1_str.clone())]);
+        let mtime = UNIX_EPOCH + std::time::Duration::from_secs(1682935200);
+
+        let mut operator = Operator::new(items);
+
+        let res = operator.apply_changes(
+            ParsedLine {
+                num: Some(1),
+                filename: file_1_str.clone(),
+                mtime: Some(mtime),
+                ..Default::default()
+            },
+            FS,
+        );
+
+        assert!(res.is_ok());
+        assert_eq!(
+            fs::metadata(file_1.path()).unwrap().modified().unwrap(),
+            mtime
+        );
+
+        // Applying the same time again does nothing.
+        let mut operator = Operator::new(ItemTable::from([(1, file_1_str.clone())]));
+        let res = operator.apply_changes(
+            ParsedLine {
+                num: Some(1),
+                filename: file_1_str,
+                mtime: Some(mtime),
+                ..Default::default()
+            },
+            Failing,
+        );
+
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn test_apply_changes_touch_unedited() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let dir_1 = temp.child("dir_1");
+        dir_1.child("file_1").touch().unwrap();
+        let listed = UNIX_EPOCH + Duration::from_secs(1682935200);
+        FS.touch(dir_1.to_str().unwrap(), listed).unwrap();
+
+        let dir_1_str = dir_1.to_str().unwrap().to_string();
+        let file_1_str = dir_1.child("file_1").to_str().unwrap().to_string();
+        let items = ItemTable::from([(1, file_1_str.clone()), (2, dir_1_str.clone())]);
+        let mut operator = Operator::new(items.clone()).snapshots(snapshot(&items));
+
+        // Renaming file_1 changes the time of dir_1, which its unedited
+        // line must not reset.
+        let res = operator.apply_changes(
+            ParsedLine {
+                num: Some(1),
+                filename: file_1_str + "_one",
+                ..Default::default()
+            },
+            FS,
+        );
+        assert!(res.is_ok());
+
+        let res = operator.apply_changes(
+            ParsedLine {
+                num: Some(2),
+                filename: dir_1_str,
+                mtime: meta::parse_mtime(&meta::format_mtime(listed)).unwrap(),
+                ..Default::default()
+            },
+            FS,
+        );
+        assert!(res.is_ok());
+        assert_ne!(
+            fs::metadata(dir_1.path()).unwrap().modified().unwrap(),
+            listed
+        );
+        assert_eq!(
+            operator.outcomes()[1].action,
+            Action::Unchanged {
+                name: dir_1.to_str().unwrap().to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_apply_changes_create() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let temp_str = temp.to_str().unwrap();
+        temp.child("file_1").touch().unwrap();
+
+        let mut operator = Operator::new(ItemTable::new());
+
+        for name in ["/new_file", "/new_dir/", "/sub/new_file"] {
+            let res = operator.apply_changes(
+                ParsedLine {
+                    num: None,
+                    filename: temp_str.to_owned() + name,
+                    ..Default::default()
+                },
+                FS,
+            );
+            assert!(res.is_ok());
+        }
+
+        temp.child("new_file").assert(predicate::path::is_file());
+        temp.child("new_dir").assert(predicate::path::is_dir());
+        temp.child("sub/new_file")
+            .assert(predicate::path::is_file());
+
+        // Existing files are never overwritten.
+        let res = operator.apply_changes(
+            ParsedLine {
+                num: None,
+                filename: temp_str.to_owned() + "/file_1",
+                ..Default::default()
+            },
+            FS,
+        );
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            temp_str.to_owned() + "/file_1 already exists"
+        );
+        assert!(operator.items.is_empty());
+        assert!(operator.dones.is_empty());
+    }
+
+    #[test]
+    fn test_preview() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let file_1 = temp.child("file_1");
+        file_1.touch().unwrap();
+        fs::set_permissions(file_1.path(), fs::Permissions::from_mode(0o644)).unwrap();
+
+        let file_1_str = file_1.to_str().unwrap().to_string();
+        let operator = Operator::new(ItemTable::from([
+            (1, file_1_str.clone()),
+            (2, "file_2".to_string()),
+        ]));
+
+        let parsed_lines = vec![
+            (
+                1,
+                ParsedLine {
+                    num: Some(1),
+                    filename: file_1_str.clone(),
+                    mode: Some(0o600),
+                    ..Default::default()
+                },
+            ),
+            (
+                2,
+                ParsedLine {
+                    num: Some(1),
+                    filename: "file_1_copy".to_string(),
+                    ..Default::default()
+                },
+            ),
+            (
+                3,
+                ParsedLine {
+                    num: Some(2),
+                    filename: "file_two".to_string(),
+                    ..Default::default()
+                },
+            ),
+            (
+                4,
+                ParsedLine {
+                    num: None,
+                    filename: "new_dir/".to_string(),
+                    ..Default::default()
+                },
+            ),
+        ];
+
+        assert_eq!(
+            operator.preview(&parsed_lines),
+            vec![
+                format!("chmod '{}' rw-------", file_1_str),
+                format!("'{}' ~> 'file_1_copy'", file_1_str),
+                "'file_2' => 'file_two'".to_string(),
+                "create 'new_dir/'".to_string(),
+            ]
+        );
+        // Nothing is changed by a preview.
+        assert_eq!(operator.items.len(), 2);
+        assert_eq!(
+            fs::metadata(file_1.path()).unwrap().permissions().mode() & 0o7777,
+            0o644
+        );
+    }
+
+    #[test]
+    fn test_preview_matches_apply() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let name = |name: &str| temp.child(name).to_str().unwrap().to_string();
+        for file in ["file_1", "file_2", "file_3", "dir_1/file_4"] {
+            temp.child(file).touch().unwrap();
+        }
+
+        let items: ItemTable = ["file_1", "file_2", "file_3", "dir_1", "dir_1/file_4"]
+            .iter()
+            .enumerate()
+            .map(|(i, file)| (i + 1, name(file)))
+            .collect();
+        let line = |num, filename| ParsedLine {
+            num,
+            filename,
+            ..Default::default()
+        };
+        // Swap file_1 and file_2, rename dir_1 to dir_one and copy file_3
+        // to dir_one, which moves the directory out of the way.
+        let parsed_lines: Vec<(usize, ParsedLine)> = [
+            line(Some(1), name("file_2")),
+            line(Some(2), name("file_1")),
+            line(Some(3), name("file_3")),
+            line(Some(4), name("dir_one")),
+            line(Some(5), name("dir_one/file_4")),
+            line(Some(3), name("dir_one")),
+            line(None, name("dir_1/")),
+        ]
+        .into_iter()
+        .enumerate()
+        .collect();
+
+        let mut operator = Operator::new(items);
+        let preview = operator.preview(&parsed_lines);
+        for (line, res) in operator.apply_all(parsed_lines, &FS) {
+            assert!(res.is_ok(), "line {}: {:?}", line, res);
+        }
+
+        let applied: Vec<String> = operator
+            .outcomes()
+            .iter()
+            .filter_map(|outcome| match &outcome.action {
+                Action::Renamed { from, to } if to.ends_with('~') => {
+                    Some(format!("'{}' -> '{}'", from, to))
+                }
+                Action::Renamed { from, to } => Some(format!("'{}' => '{}'", from, to)),
+                Action::Copied { from, to } => Some(format!("'{}' ~> '{}'", from, to)),
+                Action::Created { name } => Some(format!("create '{}'", name)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(preview, applied);
+        assert_eq!(
+            preview[0],
+            format!("'{}' -> '{}~'", name("file_2"), name("file_2"))
+        );
     }
 
     #[test]
-    fn test_apply_changes_empty_filename() {
-        let items = HashMap::from([(1, "file_1".to_string())]);
+    fn test_apply_changes_case_only() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let temp_str = temp.to_str().unwrap();
+        let readme = temp.child("Readme.md");
+        let upper = temp.child("README.md");
+        readme.touch().unwrap();
+        upper.touch().unwrap();
 
-        let mut operator = Operator::new(items.clone());
+        let items = ItemTable::from([(1, readme.to_str().unwrap().to_string())]);
+        let mut operator = Operator::new(items);
 
+        // On a case-sensitive file system these are two files, so the
+        // existing one is moved out of the way as usual.
         let res = operator.apply_changes(
             ParsedLine {
-                num: 1,
-                filename: "".to_string(),
+                num: Some(1),
+                filename: temp_str.to_owned() + "/README.md",
+                ..Default::default()
             },
             FS,
         );
 
         assert!(res.is_ok());
-        assert_eq!(operator.items, items);
-        assert!(operator.dones.is_empty());
+        temp.child("Readme.md").assert(predicate::path::missing());
+        temp.child("README.md").assert(predicate::path::exists());
+        temp.child("README.md~").assert(predicate::path::exists());
     }
 
     #[test]
-    fn test_apply_changes_src_not_exists() {
-        let items = HashMap::from([(1, "file_1".to_string())]);
+    fn test_apply_changes_hard_link() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let temp_str = temp.to_str().unwrap();
+        let readme = temp.child("Readme.md");
+        readme.touch().unwrap();
+        fs::hard_link(readme.path(), temp.child("README.md").path()).unwrap();
 
+        let items = ItemTable::from([(1, readme.to_str().unwrap().to_string())]);
         let mut operator = Operator::new(items);
 
-        // File we want to rename from does not exist.
+        // A hard link is a separate entry even though it is the same
+        // file, so it is moved out of the way like any other file.
         let res = operator.apply_changes(
             ParsedLine {
-                num: 1,
-                filename: "file_one".to_string(),
+                num: Some(1),
+                filename: temp_str.to_owned() + "/README.md",
+                ..Default::default()
             },
             FS,
         );
 
-        assert_eq!(res.unwrap_err().to_string(), "file_1 does not exist");
-        assert!(operator.items.is_empty());
-        assert!(operator.dones.is_empty());
+        assert!(res.is_ok());
+        temp.child("Readme.md").assert(predicate::path::missing());
+        temp.child("README.md").assert(predicate::path::exists());
+        temp.child("README.md~").assert(predicate::path::exists());
     }
 
     #[test]
-    fn test_apply_changes_src_exists() {
+    fn test_apply_changes_same_entry() {
         let temp = assert_fs::TempDir::new().unwrap();
         let temp_str = temp.to_str().unwrap();
-        let file_1 = temp.child("file_1");
-        file_1.touch().unwrap();
-
-        let items = HashMap::from([(1, file_1.path().to_str().unwrap().to_string())]);
+        temp.child("file_1").touch().unwrap();
 
+        let items = ItemTable::from([(1, temp_str.to_owned() + "/./file_1")]);
         let mut operator = Operator::new(items);
 
-        let want_dones = HashMap::from([(1, temp_str.to_owned() + "/file_one")]);
-
+        // A different spelling of the same path is not a swap.
         let res = operator.apply_changes(
             ParsedLine {
-                num: 1,
-                filename: temp_str.to_owned() + "/file_one",
+                num: Some(1),
+                filename: temp_str.to_owned() + "/file_1",
+                ..Default::default()
             },
             FS,
         );
 
         assert!(res.is_ok());
-        assert!(operator.items.is_empty());
-        assert_eq!(operator.dones, want_dones);
-
-        temp.child("file_1").assert(predicate::path::missing());
-        temp.child("file_one").assert(predicate::path::exists());
+        temp.child("file_1").assert(predicate::path::exists());
+        temp.child("file_1~").assert(predicate::path::missing());
     }
 
     #[test]
-    fn test_apply_changes_swap() {
+    fn test_is_alias() {
         let temp = assert_fs::TempDir::new().unwrap();
         let temp_str = temp.to_str().unwrap();
-        let file_1 = temp.child("file_1");
-        let file_2 = temp.child("file_2");
-        file_1.touch().unwrap();
-        file_2.touch().unwrap();
+        temp.child("file_1").touch().unwrap();
+        temp.child("file_2").touch().unwrap();
+        fs::hard_link(temp.child("file_1").path(), temp.child("link_1").path()).unwrap();
+
+        let file_1 = temp_str.to_owned() + "/file_1";
+        assert!(!is_alias(&file_1, &file_1));
+        assert!(is_alias(&file_1, &(temp_str.to_owned() + "//file_1")));
+        assert!(!is_alias(&file_1, &(temp_str.to_owned() + "/file_2")));
+        assert!(!is_alias(&file_1, &(temp_str.to_owned() + "/link_1")));
+        assert!(!is_alias(&file_1, &(temp_str.to_owned() + "/missing")));
+    }
 
-        let items = [(1, file_1), (2, file_2)]
-            .into_iter()
-            .map(|(k, v)| (k, v.to_str().unwrap().to_string()))
-            .collect();
+    #[test]
+    fn test_check_duplicates() {
+        let line = |num: Option<usize>, filename: &str| ParsedLine {
+            num,
+            filename: filename.to_string(),
+            ..Default::default()
+        };
+
+        let parsed_lines = vec![
+            (1, line(Some(1), "a")),
+            (2, line(Some(2), "b")),
+            (4, line(Some(3), "a")),
+        ];
+        assert_eq!(
+            check_duplicates(&parsed_lines, None)
+                .unwrap_err()
+                .to_string(),
+            "lines 1, 4: 'a' is named more than once"
+        );
 
-        let mut operator = Operator::new(items);
+        let parsed_lines = vec![
+            (1, line(Some(1), "b")),
+            (2, line(Some(2), "c")),
+            (3, line(Some(1), "c")),
+            (5, line(None, "b/")),
+            (6, line(Some(3), "")),
+            (7, line(Some(4), "")),
+        ];
+        assert_eq!(
+            check_duplicates(&parsed_lines, None)
+                .unwrap_err()
+                .to_string(),
+            "lines 1, 5: 'b' is named more than once\nlines 2, 3: 'c' is named more than once"
+        );
 
-        let want_items = HashMap::from([(2, temp_str.to_owned() + "/file_2~")]);
-        let want_dones = HashMap::from([(1, temp_str.to_owned() + "/file_2")]);
+        let parsed_lines = vec![(1, line(Some(1), "b")), (2, line(Some(2), "a"))];
+        assert!(check_duplicates(&parsed_lines, None).is_ok());
+
+        // Names relative to the base and absolute names may be the same.
+        let parsed_lines = vec![(1, line(Some(1), "a")), (2, line(Some(2), "/abs/base/a"))];
+        assert!(check_duplicates(&parsed_lines, None).is_ok());
+        assert_eq!(
+            check_duplicates(&parsed_lines, Some(Path::new("/abs/base")))
+                .unwrap_err()
+                .to_string(),
+            "lines 1, 2: 'a' is named more than once"
+        );
+    }
+
+    #[test]
+    fn test_apply_changes_into_itself() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let dir_1 = temp.child("dir_1");
+        dir_1.create_dir_all().unwrap();
+        let dir_1_str = dir_1.to_str().unwrap().to_string();
+
+        let mut operator = Operator::new(ItemTable::from([(1, dir_1_str.clone())]));
 
-        // Rename item 1 to the same name as item 2.
-        // Therefore, item 2 has to be renamed to item 2~.
         let res = operator.apply_changes(
             ParsedLine {
-                num: 1,
-                filename: temp_str.to_owned() + "/file_2",
+                num: Some(1),
+                filename: dir_1_str.clone() + "/sub/../sub/dir_1",
+                ..Default::default()
             },
-            FS,
+            Failing,
         );
 
-        assert!(res.is_ok());
-        assert_eq!(operator.items, want_items);
-        assert_eq!(operator.dones, want_dones);
-
-        temp.child("file_1").assert(predicate::path::missing());
-        temp.child("file_2").assert(predicate::path::exists());
-        temp.child("file_2~").assert(predicate::path::exists());
+        match res.unwrap_err().downcast_ref::<OpsError>() {
+            Some(OpsError::IntoItself { from, .. }) => assert_eq!(*from, dir_1_str),
+            err => panic!("unexpected {:?}", err),
+        }
+        dir_1.child("sub").assert(predicate::path::missing());
+        assert_eq!(operator.items, ItemTable::from([(1, dir_1_str)]));
+        assert!(operator.dones.is_empty());
     }
 
     #[test]
-    fn test_apply_changes_copy() {
+    fn test_is_inside() {
         let temp = assert_fs::TempDir::new().unwrap();
+        temp.child("dir").create_dir_all().unwrap();
+        temp.child("file").touch().unwrap();
         let temp_str = temp.to_str().unwrap();
-        let file_1 = temp.child("file_1");
-        file_1.touch().unwrap();
-
-        let items = [(1, file_1)]
-            .into_iter()
-            .map(|(k, v)| (k, v.to_str().unwrap().to_string()))
-            .collect();
-
-        let mut operator = Operator::new(items);
 
-        // First call changes nothing because the name is the same as in items.
-        {
-            let want_dones = HashMap::from([(1, temp_str.to_owned() + "/file_1")]);
-
-            let res = operator.apply_changes(
-                ParsedLine {
-                    num: 1,
-                    filename: temp_str.to_owned() + "/file_1",
-                },
-                FS,
-            );
-
-            assert!(res.is_ok());
-            assert!(operator.items.is_empty());
-            assert_eq!(operator.dones, want_dones);
-        }
-
-        // Second call is a copy because it has the same number.
-        {
-            let want_dones = HashMap::from([(1, temp_str.to_owned() + "/file_1_copy")]);
+        let inside = |dir: &str, name: &str| {
+            is_inside(
+                &format!("{}/{}", temp_str, dir),
+                &format!("{}/{}", temp_str, name),
+            )
+            .unwrap()
+        };
+
+        assert!(inside("dir", "dir/dir"));
+        assert!(inside("dir", "./dir//sub/"));
+        assert!(inside("dir/", "other/../dir/sub"));
+        assert!(!inside("dir", "dir"));
+        assert!(!inside("dir", "dir/.."));
+        assert!(!inside("dir", "dir2/dir"));
+        assert!(!inside("file", "file/sub"));
+    }
 
-            let res = operator.apply_changes(
-                ParsedLine {
-                    num: 1,
-                    filename: temp_str.to_owned() + "/file_1_copy",
-                },
-                FS,
-            );
+    #[test]
+    fn test_apply_changes_base() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let temp_str = temp.to_str().unwrap();
+        let file_1 = temp.child("dir/file_1");
+        file_1.touch().unwrap();
+        let file_2 = temp.child("dir/file_2");
+        file_2.touch().unwrap();
 
-            assert!(res.is_ok());
-            assert!(operator.items.is_empty());
-            assert_eq!(operator.dones, want_dones);
+        let items = ItemTable::from([
+            (1, file_1.to_str().unwrap().to_string()),
+            (2, file_2.to_str().unwrap().to_string()),
+        ]);
+        let mut operator = Operator::new(items).base(temp_str.to_owned() + "/dir");
+
+        for (num, filename) in [(1, "file_one"), (2, "../file_2")] {
+            operator
+                .apply_changes(
+                    ParsedLine {
+                        num: Some(num),
+                        filename: filename.to_string(),
+                        ..Default::default()
+                    },
+                    FS,
+                )
+                .unwrap();
         }
 
-        temp.child("file_1").assert(predicate::path::exists());
-        temp.child("file_1_copy").assert(predicate::path::exists());
+        let want_dones = ItemTable::from([
+            (1, temp_str.to_owned() + "/dir/file_one"),
+            (2, temp_str.to_owned() + "/file_2"),
+        ]);
+        assert_eq!(operator.dones, want_dones);
+        temp.child("dir/file_one").assert(predicate::path::exists());
+        temp.child("file_2").assert(predicate::path::exists());
+        temp.child("dir/file_2").assert(predicate::path::missing());
     }
 
     #[test]
-    fn test_apply_changes_rename_directory() {
+    fn test_apply_changes_trailing_slash() {
         let temp = assert_fs::TempDir::new().unwrap();
         let temp_str = temp.to_str().unwrap();
-        let temp_sub = temp.child("dir_1");
-        let temp_sub_str = temp_sub.to_str().unwrap();
-        temp_sub.create_dir_all().unwrap();
-        let file_1 = temp_sub.child("file_1");
-        file_1.touch().unwrap();
+        temp.child("dir_1").create_dir_all().unwrap();
+        temp.child("file_1").touch().unwrap();
 
-        let items = HashMap::from([
-            (1, temp_sub_str.to_owned()),
-            (2, file_1.path().to_str().unwrap().to_string()),
+        let items = ItemTable::from([
+            (1, temp_str.to_owned() + "/dir_1"),
+            (2, temp_str.to_owned() + "/file_1"),
         ]);
-
         let mut operator = Operator::new(items);
 
-        let want_items = HashMap::from([(2, temp_str.to_owned() + "/dir_one/file_1")]);
-        let want_dones = HashMap::from([(1, temp_str.to_owned() + "/dir_one")]);
-
         let res = operator.apply_changes(
             ParsedLine {
-                num: 1,
-                filename: temp_str.to_owned() + "/dir_one",
+                num: Some(1),
+                filename: temp_str.to_owned() + "/dir_one/",
+                ..Default::default()
             },
             FS,
         );
-
         assert!(res.is_ok());
-        assert_eq!(operator.items, want_items);
-        assert_eq!(operator.dones, want_dones);
+        assert_eq!(operator.dones[&1], temp_str.to_owned() + "/dir_one");
+        temp.child("dir_one").assert(predicate::path::is_dir());
 
-        temp.child("dir_1").assert(predicate::path::missing());
-        temp.child("dir_one/file_1")
-            .assert(predicate::path::exists());
+        let res = operator.apply_changes(
+            ParsedLine {
+                num: Some(2),
+                filename: temp_str.to_owned() + "/file_1/",
+                ..Default::default()
+            },
+            Failing,
+        );
+        match res.unwrap_err().downcast_ref::<OpsError>() {
+            Some(OpsError::NotDir(name)) => assert_eq!(*name, temp_str.to_owned() + "/file_1"),
+            err => panic!("unexpected {:?}", err),
+        }
+        temp.child("file_1").assert(predicate::path::is_file());
     }
 
     #[test]
-    fn test_apply_changes_subdirectory() {
+    fn test_apply_changes_stale_id() {
         let temp = assert_fs::TempDir::new().unwrap();
-        let temp_str = temp.to_str().unwrap();
         let file_1 = temp.child("file_1");
         file_1.touch().unwrap();
+        let file_1_str = file_1.to_str().unwrap().to_string();
+        let items = ItemTable::from([(1, file_1_str.clone())]);
+        let snapshots = snapshot(&items);
 
-        let items = HashMap::from([(2, file_1.path().to_str().unwrap().to_string())]);
+        // Replace file_1 with another file after it was listed.
+        let other = temp.child("other");
+        other.touch().unwrap();
+        fs::rename(&other, &file_1).unwrap();
 
-        let mut operator = Operator::new(items);
+        let mut operator = Operator::new(items.clone()).snapshots(snapshots);
 
-        let want_dones = HashMap::from([(2, temp_str.to_owned() + "/subdir/file_one")]);
+        let res = operator.apply_changes(
+            ParsedLine {
+                num: Some(1),
+                filename: file_1_str.clone() + "_one",
+                ..Default::default()
+            },
+            Failing,
+        );
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            format!("{} is no longer the file listed as 1", file_1_str)
+        );
 
+        let mut operator = Operator::new(items.clone()).snapshots(snapshot(&items));
         let res = operator.apply_changes(
             ParsedLine {
-                num: 2,
-                filename: temp_str.to_owned() + "/subdir/file_one",
+                num: Some(1),
+                filename: file_1_str.clone() + "_one",
+                ..Default::default()
             },
             FS,
         );
-
         assert!(res.is_ok());
-        assert!(operator.items.is_empty());
-        assert_eq!(operator.dones, want_dones);
-
-        temp.child("subdir/file_one")
-            .assert(predicate::path::exists());
     }
 
     #[test]
-    fn test_update_dir() {
-        // This is okay because we will catch and ignore using
-        // NotFound error.
-        let mut operator = Operator::new(
-            [(1, "./src/testdata/file2"), (2, "./src/testdata/file1")]
-                .into_iter()
-                .map(|(k, v)| (k, v.to_string()))
-                .collect(),
+    fn test_apply_changes_drift() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let file_1 = temp.child("file_1");
+        file_1.touch().unwrap();
+        let file_2 = temp.child("file_2");
+        file_2.touch().unwrap();
+        let dir_1 = temp.child("dir_1");
+        dir_1.create_dir_all().unwrap();
+        let file_4 = temp.child("file_4");
+        file_4.touch().unwrap();
+
+        let items: ItemTable = [&file_1, &file_2, &dir_1, &file_4]
+            .iter()
+            .enumerate()
+            .map(|(i, child)| (i + 1, child.to_str().unwrap().to_string()))
+            .collect();
+        let mut operator = Operator::new(items.clone()).snapshots(snapshot(&items));
+        let line = |num: usize, filename: String| ParsedLine {
+            num: Some(num),
+            filename,
+            ..Default::default()
+        };
+
+        // Changing the content of a directory is not a drift, changing the
+        // content of a file or deleting it is. Files left alone may change.
+        dir_1.child("file_3").touch().unwrap();
+        file_1.write_str("changed").unwrap();
+        fs::remove_file(&file_2).unwrap();
+        file_4.write_str("appended").unwrap();
+
+        let parsed_lines = vec![
+            (1, line(1, items[&1].clone() + "_one")),
+            (2, line(2, items[&2].clone() + "_two")),
+            (3, line(3, items[&3].clone() + "_one")),
+            (4, line(4, items[&4].clone())),
+        ];
+        let drifted: Vec<String> = operator
+            .drifted(&parsed_lines)
+            .iter()
+            .map(|e| e.to_string())
+            .collect();
+        assert_eq!(
+            drifted,
+            vec![
+                format!("{} changed since it was listed", items[&1]),
+                format!("{} does not exist", items[&2]),
+            ]
         );
 
-        let want_items = [(1, "./src/test/file2"), (2, "./src/test/file1")]
-            .into_iter()
-            .map(|(k, v)| (k, v.to_string()))
-            .collect();
+        let mut parsed_lines = parsed_lines.into_iter().map(|(_, line)| line);
+        let res = operator.apply_changes(parsed_lines.next().unwrap(), Failing);
+        match res.unwrap_err().downcast_ref::<OpsError>() {
+            Some(OpsError::Drift(name)) => assert_eq!(*name, items[&1]),
+            err => panic!("unexpected {:?}", err),
+        }
 
-        operator.update_dir("./src/testdata/", "./src/test/");
+        let res = operator.apply_changes(parsed_lines.nth(1).unwrap(), FS);
+        assert!(res.is_ok());
+        let res = operator.apply_changes(parsed_lines.next().unwrap(), Failing);
+        assert!(res.is_ok());
+        assert_eq!(
+            operator.outcomes()[2].action,
+            Action::Unchanged {
+                name: items[&4].clone()
+            }
+        );
 
-        assert_eq!(operator.items, want_items);
+        // A copy reads the file, so a changed file is not copied.
+        let mut operator = Operator::new(items.clone()).snapshots(snapshot(&items));
+        file_4.write_str("appended again").unwrap();
+        let copy = vec![
+            (1, line(4, items[&4].clone())),
+            (2, line(4, items[&4].clone() + "_copy")),
+        ];
+        assert_eq!(operator.drifted(&copy).len(), 1);
+        let mut copy = copy.into_iter().map(|(_, line)| line);
+        assert!(operator
+            .apply_changes(copy.next().unwrap(), Failing)
+            .is_ok());
+        assert!(operator
+            .apply_changes(copy.next().unwrap(), Failing)
+            .is_err());
     }
 
     #[test]
-    fn test_update_dir_odd_case() {
-        // This is okay because we will catch and ignore using
-        // NotFound error.
-        let mut operator = Operator::new(
-            [(1, "./src/testdata/file2"), (2, "./src/testdata/file1")]
-                .into_iter()
-                .map(|(k, v)| (k, v.to_string()))
-                .collect(),
+    fn test_snapshot_from_str() {
+        let snapshot: Snapshot = "2049:1234:5:-1.000000002".parse().unwrap();
+        assert_eq!(
+            snapshot,
+            Snapshot {
+                identity: Identity {
+                    dev: 2049,
+                    ino: 1234
+                },
+                size: 5,
+                mtime: (-1, 2),
+            }
+        );
+        assert_eq!(snapshot.to_string(), "2049:1234:5:-1.000000002");
+        assert!("2049:1234".parse::<Snapshot>().is_err());
+        assert!("2049:1234:5:6".parse::<Snapshot>().is_err());
+    }
+
+    #[test]
+    fn test_identity_from_str() {
+        let identity: Identity = "2049:1234".parse().unwrap();
+        assert_eq!(
+            identity,
+            Identity {
+                dev: 2049,
+                ino: 1234
+            }
         );
+        assert_eq!(identity.to_string(), "2049:1234");
+        assert!("?".parse::<Identity>().is_err());
+        assert!("1:x".parse::<Identity>().is_err());
+    }
 
-        let want_items = [(1, "./src/testfile2"), (2, "./src/testfile1")]
-            .into_iter()
-            .map(|(k, v)| (k, v.to_string()))
+    #[test]
+    fn test_apply_changes_outcomes() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let name = |name: &str| temp.child(name).to_str().unwrap().to_string();
+        for file in ["file_1", "file_2", "file_3", "file_4"] {
+            temp.child(file).touch().unwrap();
+        }
+
+        let items: ItemTable = (1..=4)
+            .map(|num| (num, name(&format!("file_{}", num))))
             .collect();
+        let mut operator = Operator::new(items.clone()).snapshots(snapshot(&items));
+        temp.child("file_4").write_str("changed").unwrap();
+
+        for (num, filename) in [
+            (Some(1), name("file_one")),
+            (Some(1), name("file_uno")),
+            (Some(2), name("file_2")),
+            (Some(3), String::new()),
+            (Some(4), name("file_four")),
+            (Some(5), name("file_5")),
+            (None, name("dir_1/")),
+        ] {
+            let _ = operator.apply_changes(
+                ParsedLine {
+                    num,
+                    filename,
+                    ..Default::default()
+                },
+                FS,
+            );
+        }
 
-        operator.update_dir("./src/testdata/", "./src/test");
+        let outcome = |num, action| Outcome { num, action };
+        assert_eq!(
+            operator.outcomes(),
+            [
+                outcome(
+                    Some(1),
+                    Action::Renamed {
+                        from: name("file_1"),
+                        to: name("file_one"),
+                    }
+                ),
+                outcome(
+                    Some(1),
+                    Action::Copied {
+                        from: name("file_one"),
+                        to: name("file_uno"),
+                    }
+                ),
+                outcome(
+                    Some(2),
+                    Action::Unchanged {
+                        name: name("file_2")
+                    }
+                ),
+                outcome(
+                    Some(3),
+                    Action::Skipped {
+                        name: name("file_3"),
+                        reason: "no new name".to_string(),
+                    }
+                ),
+                outcome(
+                    Some(4),
+                    Action::Skipped {
+                        name: name("file_4"),
+                        reason: format!("{} changed since it was listed", name("file_4")),
+                    }
+                ),
+                outcome(
+                    Some(5),
+                    Action::Failed {
+                        name: name("file_5"),
+                        reason: "unknown item number 5".to_string(),
+                    }
+                ),
+                outcome(
+                    None,
+                    Action::Created {
+                        name: name("dir_1") + "/"
+                    }
+                ),
+            ]
+        );
+    }
 
-        assert_eq!(operator.items, want_items);
+    #[test]
+    fn test_apply_all_jobs() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let name = |name: &str| temp.child(name).to_str().unwrap().to_string();
+        for (file, content) in [("file_1", "1"), ("file_2", "2"), ("file_3", "3")] {
+            temp.child(file).write_str(content).unwrap();
+        }
+
+        let items: ItemTable = (1..=3)
+            .map(|num| (num, name(&format!("file_{}", num))))
+            .collect();
+        let mut operator = Operator::new(items).jobs(4);
+
+        // Copies of 1 and 2 run together and the copy of copy_2 waits for
+        // it. The last copies move the files they replace out of the way.
+        let parsed_lines = numbered(&[
+            (1, name("file_1")),
+            (2, name("file_2")),
+            (3, name("file_3")),
+            (1, name("copy_1")),
+            (2, name("copy_2")),
+            (2, name("copy_2_2")),
+            (1, name("file_2")),
+            (2, name("file_1")),
+        ]);
+
+        let results = operator.apply_all(parsed_lines, &FS);
+        let lines: Vec<usize> = results.iter().map(|(line, _)| *line).collect();
+        assert_eq!(lines, (1..=8).collect::<Vec<_>>());
+        assert!(results.iter().all(|(_, res)| res.is_ok()));
+
+        for (file, content) in [
+            ("copy_1", "1"),
+            ("copy_2", "2"),
+            ("copy_2_2", "2"),
+            ("file_1", "2"),
+            ("file_2", "1"),
+            ("file_1~", "1"),
+            ("file_2~", "2"),
+        ] {
+            temp.child(file).assert(content);
+        }
+        assert_eq!(
+            operator.outcomes()[5].action,
+            Action::Copied {
+                from: name("copy_2"),
+                to: name("copy_2_2"),
+            }
+        );
     }
 
     #[test]
-    fn test_get_unique_tmp_name_first_try() {
+    fn test_apply_all_jobs_failed_copy() {
         let temp = assert_fs::TempDir::new().unwrap();
-        let temp_str = temp.to_str().unwrap();
         let file_1 = temp.child("file_1");
         file_1.touch().unwrap();
+        let file_1_str = file_1.to_str().unwrap().to_string();
 
-        let got = get_unique_tmp_name(&(temp_str.to_owned() + "/file_1"));
-        assert_eq!(temp_str.to_owned() + "/file_1~", got);
+        let mut operator = Operator::new(ItemTable::from([(1, file_1_str.clone())])).jobs(2);
+        let parsed_lines = numbered(&[(1, file_1_str.clone()), (1, file_1_str.clone() + "_copy")]);
+
+        let results = operator.apply_all(parsed_lines, &Failing);
+        assert!(results[0].1.is_ok());
+        assert_eq!(results[1].0, 2);
+        assert_eq!(
+            results[1].1.as_ref().unwrap_err().to_string(),
+            "unexpected copy"
+        );
+        assert_eq!(
+            operator.outcomes()[1].action,
+            Action::Failed {
+                name: file_1_str,
+                reason: "unexpected copy".to_string(),
+            }
+        );
     }
 
     #[test]
-    fn test_get_unique_tmp_name_1() {
+    fn test_apply_all_jobs_concurrent() {
+        // Copies that wait for each other, so that they only succeed when
+        // two of them run at the same time.
+        #[derive(Default)]
+        struct Meeting {
+            arrived: std::sync::Mutex<usize>,
+            all: std::sync::Condvar,
+        }
+
+        impl Operation for Meeting {
+            fn copy(&self, _: &str, _: &str) -> Result<()> {
+                let mut arrived = self.arrived.lock().unwrap();
+                *arrived += 1;
+                self.all.notify_all();
+                let (arrived, _) = self
+                    .all
+                    .wait_timeout_while(arrived, std::time::Duration::from_secs(5), |n| *n < 2)
+                    .unwrap();
+                if *arrived < 2 {
+                    bail!("copies did not run at the same time");
+                }
+                Ok(())
+            }
+        }
+
         let temp = assert_fs::TempDir::new().unwrap();
-        let temp_str = temp.to_str().unwrap();
-        let file_1a = temp.child("file_1");
-        let file_1b = temp.child("file_1~");
-        file_1a.touch().unwrap();
-        file_1b.touch().unwrap();
+        let name = |name: &str| temp.child(name).to_str().unwrap().to_string();
+        temp.child("file_1").touch().unwrap();
+        temp.child("file_2").touch().unwrap();
+
+        let mut operator =
+            Operator::new(ItemTable::from([(1, name("file_1")), (2, name("file_2"))])).jobs(2);
+        let parsed_lines = numbered(&[
+            (1, name("file_1")),
+            (1, name("copy_1")),
+            (2, name("file_2")),
+            (2, name("copy_2")),
+        ]);
 
-        let got = get_unique_tmp_name(&(temp_str.to_owned() + "/file_1"));
-        assert_eq!(temp_str.to_owned() + "/file_1~1", got);
+        let results = operator.apply_all(parsed_lines, &Meeting::default());
+        for (_, res) in results {
+            res.unwrap();
+        }
+    }
+
+    #[test]
+    fn test_apply_all_jobs_failed_copy_source() {
+        // Copies that fail for one name only.
+        struct FailingFor(&'static str);
+
+        impl Operation for FailingFor {
+            fn copy(&self, from: &str, to: &str) -> Result<()> {
+                if to.ends_with(self.0) {
+                    bail!("copy failed");
+                }
+                FS.copy(from, to)
+            }
+        }
+
+        for jobs in [1, 4] {
+            let temp = assert_fs::TempDir::new().unwrap();
+            let name = |name: &str| temp.child(name).to_str().unwrap().to_string();
+            temp.child("file_1").write_str("one").unwrap();
+
+            let mut operator = Operator::new(ItemTable::from([(1, name("file_1"))])).jobs(jobs);
+            let parsed_lines =
+                numbered(&[(1, name("file_1")), (1, name("bad")), (1, name("good"))]);
+
+            let results = operator.apply_all(parsed_lines, &FailingFor("bad"));
+            assert!(results[1].1.is_err(), "jobs {}", jobs);
+            assert!(results[2].1.is_ok(), "jobs {}", jobs);
+            temp.child("bad").assert(predicate::path::missing());
+            temp.child("good").assert("one");
+            assert_eq!(
+                operator.outcomes()[2].action,
+                Action::Copied {
+                    from: name("file_1"),
+                    to: name("good"),
+                }
+            );
+        }
     }
 }
@@ -0,0 +1,229 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// Lexically resolve `.` and `..` components, repeated separators and
+/// trailing slashes, without following symlinks.
+pub fn normalize<P: AsRef<Path>>(path: P) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.as_ref().components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(
+                    normalized.components().next_back(),
+                    Some(Component::Normal(_))
+                ) =>
+            {
+                normalized.pop();
+            }
+            Component::ParentDir if normalized.has_root() => {}
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// The normalized absolute form of path.
+pub fn absolute<P: AsRef<Path>>(path: P) -> io::Result<PathBuf> {
+    Ok(normalize(std::path::absolute(path)?))
+}
+
+/// The normalized form of path relative to the current directory.
+pub fn relative<P: AsRef<Path>>(path: P) -> io::Result<PathBuf> {
+    let path = absolute(path)?;
+    let base = absolute(env::current_dir()?)?;
+
+    let common = path
+        .components()
+        .zip(base.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut relative = PathBuf::new();
+    for _ in common..base.components().count() {
+        relative.push(Component::ParentDir);
+    }
+    for component in path.components().skip(common) {
+        relative.push(component);
+    }
+
+    if relative.as_os_str().is_empty() {
+        relative.push(Component::CurDir);
+    }
+    Ok(relative)
+}
+
+/// The form of path used to compare it with others: absolute when the
+/// current directory is known, so that `./a` and `/cwd/a` are equal.
+pub fn resolve<P: AsRef<Path>>(path: P) -> PathBuf {
+    let cwd = match path.as_ref().is_relative() {
+        true => env::current_dir().ok(),
+        false => None,
+    };
+    resolve_from(path, cwd.as_deref())
+}
+
+/// Like resolve, with the current directory looked up once by the caller
+/// to resolve many paths.
+pub fn resolve_from<P: AsRef<Path>>(path: P, cwd: Option<&Path>) -> PathBuf {
+    // Like std::path::absolute, the empty path is left alone.
+    match cwd {
+        Some(cwd) if !path.as_ref().as_os_str().is_empty() => normalize(cwd.join(path)),
+        _ => normalize(path),
+    }
+}
+
+/// The name of path relative to base, when path is below base.
+pub fn strip<P: AsRef<Path>, B: AsRef<Path>>(path: P, base: B) -> Option<String> {
+    let path = normalize(path);
+    let name = path.strip_prefix(normalize(base)).ok()?;
+
+    match name.to_str() {
+        Some("") | None => None,
+        Some(name) => Some(name.to_string()),
+    }
+}
+
+/// Resolve a name shown relative to base. A trailing slash is kept as it
+/// marks directories to create.
+pub fn join<B: AsRef<Path>>(base: B, name: &str) -> String {
+    let mut joined = normalize(base.as_ref().join(name))
+        .to_string_lossy()
+        .into_owned();
+    if joined.is_empty() {
+        joined.push('.');
+    }
+    if name.ends_with('/') {
+        joined.push('/');
+    }
+    joined
+}
+
+/// The deepest directory containing all paths, or None if they have
+/// nothing in common.
+pub fn common_dir(paths: &[String]) -> Option<PathBuf> {
+    let mut paths = paths.iter().map(|path| {
+        let mut dir = normalize(path);
+        dir.pop();
+        dir
+    });
+
+    let mut common = paths.next()?;
+    for dir in paths {
+        let len = common
+            .components()
+            .zip(dir.components())
+            .take_while(|(a, b)| a == b)
+            .count();
+        common = common.components().take(len).collect();
+    }
+
+    if common.as_os_str().is_empty() {
+        return None;
+    }
+    Some(common)
+}
+
+/// Whether path is a directory itself rather than a symlink to one.
+pub fn is_dir<P: AsRef<Path>>(path: P) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|metadata| metadata.is_dir())
+}
+
+/// Whether a and b name the same path, regardless of spelling.
+pub fn same<P: AsRef<Path>>(a: P, b: P) -> bool {
+    let (a, b) = (a.as_ref(), b.as_ref());
+    if a.as_os_str().is_empty() || b.as_os_str().is_empty() {
+        return a == b;
+    }
+    let (a, b) = (normalize(a), normalize(b));
+
+    // The current directory is only looked up to compare absolute paths
+    // with relative ones, or relative paths leaving it.
+    let anchored = |path: &Path| path.is_absolute() || !path.starts_with("..");
+    if a.is_absolute() == b.is_absolute() && anchored(&a) && anchored(&b) {
+        return a == b;
+    }
+    resolve(a) == resolve(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        for (path, want) in [
+            ("./a//b/./c/", "a/b/c"),
+            ("a/../../b", "../b"),
+            ("/a/../../b", "/b"),
+            ("../a/..", ".."),
+            (".", ""),
+        ] {
+            assert_eq!(normalize(path), Path::new(want), "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_absolute_and_relative() {
+        let cwd = env::current_dir().unwrap();
+
+        assert_eq!(absolute("./src//lib.rs").unwrap(), cwd.join("src/lib.rs"));
+        assert_eq!(absolute("/a/./b/../c/").unwrap(), Path::new("/a/c"));
+
+        assert_eq!(relative("./src//lib.rs").unwrap(), Path::new("src/lib.rs"));
+        assert_eq!(relative(cwd.join("src/../a")).unwrap(), Path::new("a"));
+        assert_eq!(relative("./").unwrap(), Path::new("."));
+
+        let parent = cwd.parent().unwrap();
+        assert_eq!(relative(parent.join("x")).unwrap(), Path::new("../x"));
+    }
+
+    #[test]
+    fn test_strip_and_join() {
+        assert_eq!(
+            strip("./some/deep/path/a", "some/deep/path/").as_deref(),
+            Some("a")
+        );
+        assert_eq!(strip("./some/deep/path", "some/deep/path"), None);
+        assert_eq!(strip("./some/a", "some/deep"), None);
+
+        assert_eq!(join("some/deep", "b"), "some/deep/b");
+        assert_eq!(join("some/deep", "../b/"), "some/b/");
+        assert_eq!(join("some", ".."), ".");
+        assert_eq!(join("some", "/abs"), "/abs");
+    }
+
+    #[test]
+    fn test_common_dir() {
+        let paths = |paths: &[&str]| paths.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            common_dir(&paths(&["./a/b/c", "a/b/d/e", "./a/b//f"])),
+            Some(PathBuf::from("a/b"))
+        );
+        assert_eq!(common_dir(&paths(&["/x/y/z"])), Some(PathBuf::from("/x/y")));
+        assert_eq!(common_dir(&paths(&["a/b", "c/d"])), None);
+        assert_eq!(common_dir(&paths(&["a/b", "/a/b"])), None);
+        assert_eq!(common_dir(&paths(&["b"])), None);
+        assert_eq!(common_dir(&[]), None);
+    }
+
+    #[test]
+    fn test_same() {
+        let cwd = env::current_dir().unwrap();
+
+        assert!(same("./src/testdata/", "src//testdata"));
+        assert!(same(Path::new("src/lib.rs"), &cwd.join("src/lib.rs")));
+        assert!(!same("./src/testdata", "./src/test"));
+
+        let name = cwd.file_name().unwrap().to_str().unwrap();
+        assert!(same(format!("../{}/src", name), "src".to_string()));
+        assert_eq!(
+            resolve_from("a/../b", Some(Path::new("/x"))),
+            Path::new("/x/b")
+        );
+        assert_eq!(resolve_from("a/../b", None), Path::new("b"));
+    }
+}
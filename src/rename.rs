@@ -0,0 +1,559 @@
+use crate::editor::ParsedLine;
+use crate::items::ItemTable;
+use crate::{meta, ops};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local};
+use deunicode::deunicode_with_tofu;
+use regex::{Regex, RegexBuilder};
+use std::fs;
+use std::path::Path;
+use unicode_normalization::UnicodeNormalization;
+
+/// A Perl style substitution such as `s/IMG_(\d+)/photo-$1/g`. Like the
+/// other renames it only changes the last component of a path, so that
+/// anchors such as `^` match the start of the name.
+#[derive(Debug)]
+pub struct Substitution {
+    regex: Regex,
+    replacement: String,
+    global: bool,
+}
+
+impl Substitution {
+    pub fn parse(expr: &str) -> Result<Self> {
+        let invalid = || anyhow!("invalid substitution {:?}", expr);
+
+        let mut chars = expr.strip_prefix('s').ok_or_else(invalid)?.chars();
+        let delimiter = chars
+            .next()
+            .filter(|c| !c.is_alphanumeric() && !c.is_whitespace() && *c != '\\')
+            .ok_or_else(invalid)?;
+
+        let pattern = split_part(&mut chars, delimiter).ok_or_else(invalid)?;
+        let replacement = split_part(&mut chars, delimiter).ok_or_else(invalid)?;
+
+        let mut builder = RegexBuilder::new(&pattern);
+        let mut global = false;
+        for flag in chars {
+            match flag {
+                'g' => global = true,
+                'i' => {
+                    builder.case_insensitive(true);
+                }
+                'x' => {
+                    builder.ignore_whitespace(true);
+                }
+                _ => return Err(anyhow!("unknown flag {:?} in {:?}", flag, expr)),
+            }
+        }
+
+        Ok(Self {
+            regex: builder.build()?,
+            replacement: convert_replacement(&replacement),
+            global,
+        })
+    }
+
+    pub fn apply(&self, name: &str) -> String {
+        let (parent, base) = split_name(name);
+        let base = if self.global {
+            self.regex.replace_all(base, self.replacement.as_str())
+        } else {
+            self.regex.replace(base, self.replacement.as_str())
+        };
+        parent.to_string() + &base
+    }
+}
+
+// Read up to the next unescaped delimiter. An escaped delimiter is
+// unescaped, other escapes are kept for the regex.
+fn split_part<I: Iterator<Item = char>>(chars: &mut I, delimiter: char) -> Option<String> {
+    let mut part = String::new();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next()? {
+                c if c == delimiter => part.push(c),
+                c => {
+                    part.push('\\');
+                    part.push(c);
+                }
+            },
+            c if c == delimiter => return Some(part),
+            c => part.push(c),
+        }
+    }
+    None
+}
+
+// Convert a Perl replacement to the syntax of the regex crate. `$1` and
+// `\1` become `${1}` so that `$1abc` is not read as a group named `1abc`,
+// and `\$` is a literal dollar sign.
+fn convert_replacement(replacement: &str) -> String {
+    let mut converted = String::new();
+    let mut chars = replacement.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '$' | '\\' if chars.peek().is_some_and(char::is_ascii_digit) => {
+                let mut group = String::new();
+                while let Some(d) = chars.next_if(char::is_ascii_digit) {
+                    group.push(d);
+                }
+                converted.push_str(&format!("${{{}}}", group));
+            }
+            '\\' if chars.peek() == Some(&'$') => {
+                chars.next();
+                converted.push_str("$$");
+            }
+            '\\' if chars.peek() == Some(&'\\') => {
+                chars.next();
+                converted.push('\\');
+            }
+            c => converted.push(c),
+        }
+    }
+    converted
+}
+
+/// Built-in transformations. They only change the last component of a
+/// path, never the directories leading to it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Transform {
+    Lower,
+    Upper,
+    /// Lowercase ASCII words separated by `-`, keeping the extension.
+    Slugify,
+    /// Transliterate to ASCII.
+    Ascii,
+    /// Normalize to Unicode NFC, e.g. names created on macOS.
+    UnicodeNfc,
+}
+
+impl Transform {
+    pub fn apply(&self, name: &str) -> String {
+        let (parent, base) = split_name(name);
+        let base = match self {
+            Transform::Lower => base.to_lowercase(),
+            Transform::Upper => base.to_uppercase(),
+            Transform::Slugify => slugify(base),
+            Transform::Ascii => to_ascii(base),
+            Transform::UnicodeNfc => base.nfc().collect(),
+        };
+        parent.to_string() + &base
+    }
+}
+
+// Split name after its last slash, into the directories leading to it and
+// its last component.
+fn split_name(name: &str) -> (&str, &str) {
+    match name.rfind('/') {
+        Some(idx) => name.split_at(idx + 1),
+        None => ("", name),
+    }
+}
+
+// Transliteration may produce a slash, which must not end up in a name.
+fn to_ascii(name: &str) -> String {
+    deunicode_with_tofu(name, "_").replace('/', "_")
+}
+
+// Leading dots are kept so that hidden files stay hidden.
+fn slugify(name: &str) -> String {
+    let hidden = name.len() - name.trim_start_matches('.').len();
+    let (dots, name) = name.split_at(hidden);
+
+    let (stem, ext) = match name.rfind('.') {
+        Some(idx) if idx > 0 => name.split_at(idx),
+        _ => (name, ""),
+    };
+
+    let mut slug = String::new();
+    for c in to_ascii(stem).to_lowercase().chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug = slug.trim_end_matches('-');
+
+    if slug.is_empty() {
+        return dots.to_string() + name;
+    }
+
+    let ext: String = to_ascii(ext)
+        .to_lowercase()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '.')
+        .collect();
+    dots.to_string() + slug + &ext
+}
+
+#[derive(Debug, PartialEq)]
+enum Part {
+    Literal(String),
+    Counter { width: usize, zero: bool },
+    Stem,
+    Ext,
+    Parent,
+    Date,
+    Size,
+}
+
+/// A template such as `holiday-{n:03}{ext}` for the last component of a
+/// path. Placeholders are `{n}` for the position in the listing, starting
+/// from 1 and optionally padded as in `{n:3}` or `{n:03}`, `{stem}` and
+/// `{ext}` for the original name without and with only its extension,
+/// `{parent}` for the name of the directory, `{date}` for the modification
+/// date and `{size}` for the size in bytes. `{{` and `}}` are literal
+/// braces.
+#[derive(Debug)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Self> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let (placeholder, remain) = chars
+                        .as_str()
+                        .split_once('}')
+                        .ok_or_else(|| anyhow!("unclosed {{ in template {:?}", template))?;
+                    chars = remain.chars();
+
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(parse_placeholder(placeholder)?);
+                }
+                '}' => return Err(anyhow!("unmatched }} in template {:?}", template)),
+                c => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(Self { parts })
+    }
+
+    /// Render the new name of the nth entry of the listing. The result
+    /// replaces the last component of name.
+    pub fn render(&self, n: usize, name: &str) -> String {
+        let path = Path::new(name);
+        let base = path.file_name().unwrap_or_default().to_string_lossy();
+        let (stem, ext) = match base.rfind('.') {
+            Some(idx) if idx > 0 => base.split_at(idx),
+            _ => (base.as_ref(), ""),
+        };
+        let metadata = fs::metadata(path).ok();
+
+        let mut rendered = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(literal) => rendered.push_str(literal),
+                Part::Counter { width, zero: true } => {
+                    rendered.push_str(&format!("{:0w$}", n, w = width))
+                }
+                Part::Counter { width, zero: false } => {
+                    rendered.push_str(&format!("{:w$}", n, w = width))
+                }
+                Part::Stem => rendered.push_str(stem),
+                Part::Ext => rendered.push_str(ext),
+                Part::Parent => rendered.push_str(&parent_name(path)),
+                Part::Date => match metadata.as_ref().and_then(|m| m.modified().ok()) {
+                    Some(mtime) => rendered.push_str(
+                        &DateTime::<Local>::from(mtime)
+                            .format("%Y-%m-%d")
+                            .to_string(),
+                    ),
+                    None => rendered.push_str(meta::UNKNOWN),
+                },
+                Part::Size => match &metadata {
+                    Some(metadata) => rendered.push_str(&metadata.len().to_string()),
+                    None => rendered.push_str(meta::UNKNOWN),
+                },
+            }
+        }
+
+        split_name(name).0.to_string() + &rendered
+    }
+}
+
+fn parse_placeholder(placeholder: &str) -> Result<Part> {
+    let part = match placeholder {
+        "n" => Part::Counter {
+            width: 0,
+            zero: false,
+        },
+        "stem" => Part::Stem,
+        "ext" => Part::Ext,
+        "parent" => Part::Parent,
+        "date" => Part::Date,
+        "size" => Part::Size,
+        _ => match placeholder.strip_prefix("n:") {
+            Some(spec) if !spec.is_empty() && spec.chars().all(|c| c.is_ascii_digit()) => {
+                Part::Counter {
+                    width: spec.parse()?,
+                    zero: spec.starts_with('0'),
+                }
+            }
+            _ => return Err(anyhow!("unknown placeholder {{{}}}", placeholder)),
+        },
+    };
+    Ok(part)
+}
+
+// Name of the directory containing path, resolving names such as `.`.
+fn parent_name(path: &Path) -> String {
+    let parent = match path.parent() {
+        Some(parent) if parent != Path::new("") => parent,
+        _ => Path::new("."),
+    };
+    let parent = fs::canonicalize(parent).unwrap_or_else(|_| parent.to_path_buf());
+
+    parent
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// Compute a new name for every item with rename and return the lines
+/// for the items whose name changes, as if they were edited in the file
+/// list. Items are visited in listing order.
+pub fn rename_with<F>(items: &ItemTable, mut rename: F) -> Vec<(usize, ParsedLine)>
+where
+    F: FnMut(&str) -> String,
+{
+    items
+        .iter()
+        .enumerate()
+        .filter_map(|(i, (num, name))| {
+            let new_name = rename(name);
+
+            (new_name != *name).then(|| {
+                (
+                    i + 1,
+                    ParsedLine {
+                        num: Some(num),
+                        filename: new_name,
+                        ..Default::default()
+                    },
+                )
+            })
+        })
+        .collect()
+}
+
+/// Check that the lines of rename_with leave no two items with the same
+/// name, counting the items they keep as they are.
+pub fn check_names(items: &ItemTable, parsed_lines: &[(usize, ParsedLine)]) -> Result<()> {
+    let mut renamed = parsed_lines.iter().peekable();
+    let lines: Vec<_> = items
+        .iter()
+        .enumerate()
+        .map(|(i, (num, name))| {
+            let filename = match renamed.next_if(|(line, _)| *line == i + 1) {
+                Some((_, parsed_line)) => parsed_line.filename.clone(),
+                None => name.clone(),
+            };
+            let parsed_line = ParsedLine {
+                num: Some(num),
+                filename,
+                ..Default::default()
+            };
+            (i + 1, parsed_line)
+        })
+        .collect();
+    ops::check_duplicates(&lines, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::prelude::*;
+
+    #[test]
+    fn test_substitution() {
+        let sub = Substitution::parse(r"s/IMG_(\d+)/photo-$1/").unwrap();
+        assert_eq!(sub.apply("./IMG_0042.jpg"), "./photo-0042.jpg");
+        assert_eq!(sub.apply("./notes.txt"), "./notes.txt");
+
+        let sub = Substitution::parse(r"s/(\d+)/<$1a>/g").unwrap();
+        assert_eq!(sub.apply("1_22"), "<1a>_<22a>");
+
+        // Missing groups are replaced with nothing.
+        let sub = Substitution::parse(r"s#a-b#\2c\$#i").unwrap();
+        assert_eq!(sub.apply("x/A-B"), "x/c$");
+
+        let sub = Substitution::parse(r"s|\.JPE?G$|.jpg|i").unwrap();
+        assert_eq!(sub.apply("a.jpeg"), "a.jpg");
+
+        let sub = Substitution::parse(r"s,a\,b,a_b,").unwrap();
+        assert_eq!(sub.apply("x/a,b"), "x/a_b");
+
+        // Only the last component is matched, anchors included.
+        let sub = Substitution::parse(r"s/^/x-/").unwrap();
+        assert_eq!(sub.apply("./IMG_1.jpg"), "./x-IMG_1.jpg");
+        assert_eq!(sub.apply("IMG_1.jpg"), "x-IMG_1.jpg");
+
+        let sub = Substitution::parse(r"s/dir/folder/g").unwrap();
+        assert_eq!(sub.apply("dir/dir_1"), "dir/folder_1");
+    }
+
+    #[test]
+    fn test_substitution_invalid() {
+        for expr in ["", "y/a/b/", "s/a/b", "sabc", "s/(/b/", "s{a}{b}"] {
+            assert!(Substitution::parse(expr).is_err(), "{:?}", expr);
+        }
+        assert_eq!(
+            Substitution::parse("s/a/b/q").unwrap_err().to_string(),
+            r#"unknown flag 'q' in "s/a/b/q""#
+        );
+    }
+
+    #[test]
+    fn test_rename_with() {
+        let items = ItemTable::from([
+            (1, "IMG_1.jpg".to_string()),
+            (2, "notes.txt".to_string()),
+            (3, "IMG_3.jpg".to_string()),
+        ]);
+
+        let lines = rename_with(&items, |name| name.replace("IMG_", "photo-"));
+        assert_eq!(
+            lines,
+            vec![
+                (
+                    1,
+                    ParsedLine {
+                        num: Some(1),
+                        filename: "photo-1.jpg".to_string(),
+                        ..Default::default()
+                    }
+                ),
+                (
+                    3,
+                    ParsedLine {
+                        num: Some(3),
+                        filename: "photo-3.jpg".to_string(),
+                        ..Default::default()
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_names() {
+        let items = ItemTable::from([(1, "./A.txt".to_string()), (2, "./a.txt".to_string())]);
+
+        let lines = rename_with(&items, |name| name.to_lowercase());
+        assert_eq!(
+            check_names(&items, &lines).unwrap_err().to_string(),
+            "lines 1, 2: './a.txt' is named more than once"
+        );
+
+        // Swapping names leaves each name to one item.
+        let lines = rename_with(&items, |name| match name {
+            "./A.txt" => "./a.txt".to_string(),
+            _ => "./A.txt".to_string(),
+        });
+        assert!(check_names(&items, &lines).is_ok());
+    }
+
+    #[test]
+    fn test_transform() {
+        let cases = [
+            (
+                Transform::Lower,
+                "./Photos/IMG_01.JPG",
+                "./Photos/img_01.jpg",
+            ),
+            (Transform::Upper, "readme.md", "README.MD"),
+            (Transform::Ascii, "./Ünïcödé ß.txt", "./Unicode ss.txt"),
+            (
+                Transform::Slugify,
+                "./Docs/My Photo (1) Über.JPG",
+                "./Docs/my-photo-1-uber.jpg",
+            ),
+            (Transform::Slugify, ".My Config", ".my-config"),
+            (Transform::Slugify, "???.txt", "???.txt"),
+            (Transform::UnicodeNfc, "Cafe\u{301}.txt", "Caf\u{e9}.txt"),
+        ];
+
+        for (transform, name, want) in cases {
+            assert_eq!(transform.apply(name), want, "{:?} {}", transform, name);
+        }
+    }
+
+    #[test]
+    fn test_template() {
+        let template = Template::parse("holiday-{n:03}{ext}").unwrap();
+        assert_eq!(
+            template.render(7, "./trip/IMG_1.JPG"),
+            "./trip/holiday-007.JPG"
+        );
+        assert_eq!(template.render(1234, "IMG_1"), "holiday-1234");
+
+        let template = Template::parse("{{{stem}}}_{n:2}_{n}").unwrap();
+        assert_eq!(template.render(3, "a/b.tar.gz"), "a/{b.tar}_ 3_3");
+
+        let template = Template::parse("{parent}-{stem}").unwrap();
+        assert_eq!(
+            template.render(1, "./src/testdata/file1"),
+            "./src/testdata/testdata-file1"
+        );
+
+        let template = Template::parse("{size}-{date}").unwrap();
+        assert_eq!(template.render(1, "does_not_exist"), "?-?");
+    }
+
+    #[test]
+    fn test_template_metadata() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let file_1 = temp.child("file_1.txt");
+        file_1.write_str("hello").unwrap();
+
+        let template = Template::parse("{date}_{size}{ext}").unwrap();
+        let rendered = template.render(1, file_1.to_str().unwrap());
+        let today = Local::now().format("%Y-%m-%d").to_string();
+
+        assert_eq!(
+            rendered,
+            format!("{}/{}_5.txt", temp.to_str().unwrap(), today)
+        );
+    }
+
+    #[test]
+    fn test_template_invalid() {
+        assert_eq!(
+            Template::parse("{n").unwrap_err().to_string(),
+            "unclosed { in template \"{n\""
+        );
+        assert_eq!(
+            Template::parse("n}").unwrap_err().to_string(),
+            "unmatched } in template \"n}\""
+        );
+        assert_eq!(
+            Template::parse("{name}").unwrap_err().to_string(),
+            "unknown placeholder {name}"
+        );
+        assert!(Template::parse("{n:x}").is_err());
+    }
+}
@@ -0,0 +1,403 @@
+use crate::editor::{self, Format, ParsedLine};
+use crate::ops::{self, Operator, OpsError, Outcome};
+use anyhow::Result;
+use std::io::{BufRead, Cursor, Write};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum SessionError {
+    #[error("{0}\nunable to parse file list")]
+    Parse(String),
+
+    #[error("{0}\nconflicting names in file list")]
+    Conflict(String),
+
+    #[error("{}\nfiles changed since they were listed", join(.0))]
+    Drift(Vec<OpsError>),
+}
+
+fn join(errors: &[OpsError]) -> String {
+    let errors: Vec<String> = errors.iter().map(|err| err.to_string()).collect();
+    errors.join("\n")
+}
+
+type Retry = Box<dyn FnMut(&anyhow::Error) -> Result<bool>>;
+
+/// A session lists sources as a file list, lets a caller supplied step edit
+/// it and applies the edited list, the way the command line tool does with
+/// an editor.
+///
+/// ```no_run
+/// use vidirr::Session;
+///
+/// let report = Session::new(vec!["./photos/IMG_1.JPG".to_string()])
+///     .verbose(true)
+///     .run(|list| Ok(list.replace("IMG_", "holiday-")))?;
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub struct Session {
+    sources: Vec<String>,
+    format: Format,
+    verbose: bool,
+    verbose_to_stderr: bool,
+    jobs: usize,
+    abort_on_drift: bool,
+    retry: Option<Retry>,
+}
+
+impl Session {
+    pub fn new(sources: Vec<String>) -> Self {
+        Self {
+            sources,
+            format: Format::default(),
+            verbose: false,
+            verbose_to_stderr: false,
+            jobs: 1,
+            abort_on_drift: false,
+            retry: None,
+        }
+    }
+
+    /// Format of the file list.
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Print every change made to the file system.
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    /// Print the changes of verbose to stderr instead of stdout.
+    pub fn verbose_to_stderr(mut self, verbose_to_stderr: bool) -> Self {
+        self.verbose_to_stderr = verbose_to_stderr;
+        self
+    }
+
+    /// Number of copies run at once.
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs;
+        self
+    }
+
+    /// Apply nothing if a file to rename, copy or update changed since it
+    /// was listed, instead of only skipping it.
+    pub fn abort_on_drift(mut self, abort_on_drift: bool) -> Self {
+        self.abort_on_drift = abort_on_drift;
+        self
+    }
+
+    /// Decide whether an edited list that cannot be applied is handed back
+    /// to the edit step, to fix it without losing the other edits.
+    pub fn retry<R>(mut self, retry: R) -> Self
+    where
+        R: FnMut(&anyhow::Error) -> Result<bool> + 'static,
+    {
+        self.retry = Some(Box::new(retry));
+        self
+    }
+
+    /// Write the file list, pass it to edit and turn the edited list into
+    /// a plan. edit is called again with its own output when the list is
+    /// invalid and retry allows it.
+    pub fn edit<E>(mut self, mut edit: E) -> Result<Plan>
+    where
+        E: FnMut(String) -> Result<String>,
+    {
+        let mut buffer = Vec::new();
+        let items = editor::write_with_format(&mut buffer, &self.sources, &self.format)?;
+        let snapshots = ops::snapshot(&items);
+        let mut list = String::from_utf8(buffer)?;
+
+        let parsed_lines = loop {
+            list = edit(list)?;
+            match parse(Cursor::new(&list), &self.format) {
+                Ok(parsed_lines) => break parsed_lines,
+                Err(err) => match self.retry.as_mut() {
+                    Some(retry) => {
+                        if !retry(&err)? {
+                            return Err(err);
+                        }
+                    }
+                    None => return Err(err),
+                },
+            }
+        };
+
+        let mut operator = Operator::new(items)
+            .snapshots(snapshots)
+            .verbose(self.verbose)
+            .verbose_to_stderr(self.verbose_to_stderr)
+            .jobs(self.jobs);
+        if let Some(base) = &self.format.base {
+            operator = operator.base(base);
+        }
+        Ok(Plan::new(operator, parsed_lines).abort_on_drift(self.abort_on_drift))
+    }
+
+    /// Edit the file list and apply it.
+    pub fn run<E>(self, edit: E) -> Result<Report>
+    where
+        E: FnMut(String) -> Result<String>,
+    {
+        self.edit(edit)?.apply()
+    }
+}
+
+/// Parse an edited file list and check that no two lines name the same
+/// file.
+pub fn parse<R: BufRead>(reader: R, format: &Format) -> Result<Vec<(usize, ParsedLine)>> {
+    let parsed_lines =
+        editor::parse_buffer(reader, format).map_err(|err| SessionError::Parse(err.to_string()))?;
+    ops::check_duplicates(&parsed_lines, format.base.as_deref())
+        .map_err(|err| SessionError::Conflict(err.to_string()))?;
+    Ok(parsed_lines)
+}
+
+/// The changes of an edited file list, ready to be previewed or applied.
+pub struct Plan {
+    operator: Operator,
+    parsed_lines: Vec<(usize, ParsedLine)>,
+    abort_on_drift: bool,
+}
+
+impl Plan {
+    pub fn new(operator: Operator, parsed_lines: Vec<(usize, ParsedLine)>) -> Self {
+        Self {
+            operator,
+            parsed_lines,
+            abort_on_drift: false,
+        }
+    }
+
+    /// Apply nothing if a file to change drifted, see `Session::abort_on_drift`.
+    pub fn abort_on_drift(mut self, abort_on_drift: bool) -> Self {
+        self.abort_on_drift = abort_on_drift;
+        self
+    }
+
+    /// Describe the changes without applying them.
+    pub fn preview(&self) -> Vec<String> {
+        self.operator.preview(&self.parsed_lines)
+    }
+
+    /// Apply every line, carrying on after lines that fail.
+    pub fn apply(mut self) -> Result<Report> {
+        if self.abort_on_drift {
+            let drifted = self.operator.drifted(&self.parsed_lines);
+            if !drifted.is_empty() {
+                return Err(SessionError::Drift(drifted).into());
+            }
+        }
+
+        let mut report = Report::default();
+        for (line, res) in self.operator.apply_all(self.parsed_lines, &ops::FS) {
+            match res {
+                Ok(_) => report.applied.push(line),
+                Err(err) => report.failed.push((line, err)),
+            }
+        }
+        report.outcomes = self.operator.outcomes().to_vec();
+        Ok(report)
+    }
+}
+
+/// What applying a plan did, by line of the edited file list.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub applied: Vec<usize>,
+    pub failed: Vec<(usize, anyhow::Error)>,
+    /// What happened to each item, in the order of the lines. Files moved
+    /// out of the way of a rename come before the line that moved them.
+    pub outcomes: Vec<Outcome>,
+}
+
+impl Report {
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty()
+    }
+
+    /// Write the outcomes as a JSON array.
+    pub fn write_json<W: Write>(&self, mut writer: W) -> Result<()> {
+        serde_json::to_writer_pretty(&mut writer, &self.outcomes)?;
+        writeln!(writer)?;
+        Ok(())
+    }
+
+    /// Write the outcomes as JSON Lines, one object per outcome.
+    pub fn write_jsonl<W: Write>(&self, mut writer: W) -> Result<()> {
+        for outcome in &self.outcomes {
+            serde_json::to_writer(&mut writer, outcome)?;
+            writeln!(writer)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ops::Action;
+    use assert_fs::prelude::*;
+    use predicates::prelude::*;
+
+    #[test]
+    fn test_run() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        temp.child("file_1").touch().unwrap();
+        temp.child("file_2").touch().unwrap();
+        let sources = vec![
+            temp.child("file_1").to_str().unwrap().to_string(),
+            temp.child("file_2").to_str().unwrap().to_string(),
+        ];
+
+        let report = Session::new(sources)
+            .run(|list| Ok(list.replace("file_1", "file_one")))
+            .unwrap();
+
+        assert!(report.is_success());
+        assert_eq!(report.applied, vec![1, 2]);
+        temp.child("file_one").assert(predicate::path::exists());
+        temp.child("file_1").assert(predicate::path::missing());
+        temp.child("file_2").assert(predicate::path::exists());
+    }
+
+    #[test]
+    fn test_run_base_unedited() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let base = temp.child("some/deep");
+        base.child("file_1").touch().unwrap();
+        base.child("dir/file_2").touch().unwrap();
+        temp.child("elsewhere").touch().unwrap();
+
+        // A relative name outside base cannot be shown relative to it.
+        let elsewhere = crate::path::relative(temp.child("elsewhere").path()).unwrap();
+        let sources = vec![
+            base.child("file_1").to_str().unwrap().to_string(),
+            base.child("dir/file_2").to_str().unwrap().to_string(),
+            elsewhere.to_str().unwrap().to_string(),
+        ];
+        let format = Format {
+            base: Some(base.to_path_buf()),
+            ..Default::default()
+        };
+
+        let plan = Session::new(sources).format(format).edit(Ok).unwrap();
+        assert!(plan.preview().is_empty());
+
+        let report = plan.apply().unwrap();
+        assert!(report.is_success());
+        assert_eq!(report.outcomes.len(), 3);
+        for outcome in report.outcomes {
+            assert!(matches!(outcome.action, Action::Unchanged { .. }));
+        }
+        temp.child("elsewhere").assert(predicate::path::exists());
+    }
+
+    #[test]
+    fn test_edit_retry() {
+        let sources = vec!["file_1".to_string(), "file_2".to_string()];
+
+        let mut calls = Vec::new();
+        let plan = Session::new(sources)
+            .retry(|err| Ok(err.to_string().starts_with("line 3")))
+            .edit(|list| {
+                calls.push(list.clone());
+                match calls.len() {
+                    1 => Ok(list + "+\n"),
+                    _ => Ok(list.replace("2 file_2\n+\n", "2 file_two\n")),
+                }
+            })
+            .unwrap();
+
+        assert_eq!(
+            calls,
+            vec!["1 file_1\n2 file_2\n", "1 file_1\n2 file_2\n+\n"]
+        );
+        assert_eq!(plan.preview(), vec!["'file_2' => 'file_two'"]);
+    }
+
+    #[test]
+    fn test_edit_invalid() {
+        let sources = vec!["file_1".to_string(), "file_2".to_string()];
+
+        let err = Session::new(sources.clone())
+            .edit(|list| Ok(list.replace("2 file_2", "2 file_1")))
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "lines 1, 2: 'file_1' is named more than once\nconflicting names in file list"
+        );
+        assert!(matches!(
+            err.downcast_ref::<SessionError>(),
+            Some(SessionError::Conflict(_))
+        ));
+
+        let err = Session::new(sources)
+            .retry(|_| Ok(false))
+            .edit(|list| Ok(list + "+\n"))
+            .err()
+            .unwrap();
+        assert!(matches!(
+            err.downcast_ref::<SessionError>(),
+            Some(SessionError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn test_apply_abort_on_drift() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let file_1 = temp.child("file_1");
+        file_1.touch().unwrap();
+        let sources = vec![file_1.to_str().unwrap().to_string()];
+
+        let plan = Session::new(sources)
+            .abort_on_drift(true)
+            .edit(|list| {
+                file_1.write_str("changed").unwrap();
+                Ok(list.replace("file_1", "file_one"))
+            })
+            .unwrap();
+
+        let err = plan.apply().err().unwrap();
+        assert!(matches!(
+            err.downcast_ref::<SessionError>(),
+            Some(SessionError::Drift(drifted)) if drifted.len() == 1
+        ));
+        file_1.assert(predicate::path::exists());
+    }
+
+    #[test]
+    fn test_report_write() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        temp.child("file_1").touch().unwrap();
+        let file_1 = temp.child("file_1").to_str().unwrap().to_string();
+        let file_one = temp.child("file_one").to_str().unwrap().to_string();
+        let dir_1 = temp.child("dir_1").to_str().unwrap().to_string() + "/";
+
+        let report = Session::new(vec![file_1.clone()])
+            .run(|list| Ok(format!("{}+ {}\n", list.replace(&file_1, &file_one), dir_1)))
+            .unwrap();
+
+        let mut json = Vec::new();
+        report.write_jsonl(&mut json).unwrap();
+        assert_eq!(
+            String::from_utf8(json).unwrap(),
+            format!(
+                "{{\"num\":1,\"action\":\"renamed\",\"from\":\"{}\",\"to\":\"{}\"}}\n\
+                 {{\"action\":\"created\",\"name\":\"{}\"}}\n",
+                file_1, file_one, dir_1
+            )
+        );
+
+        let mut json = Vec::new();
+        report.write_json(&mut json).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(value[0]["action"], "renamed");
+        assert_eq!(value[1]["name"], dir_1.as_str());
+        temp.child("dir_1").assert(predicate::path::is_dir());
+    }
+}